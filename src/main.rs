@@ -1,11 +1,14 @@
 mod md2tex;
+// Vendored from html5ever, kept as close to upstream as possible.
+#[allow(dead_code, clippy::all)]
 mod rcdom;
-
 use mdbook::book::BookItem;
 use mdbook::config::Config as MdConfig;
+use mdbook::config::{BookConfig, TextDirection};
 use mdbook::renderer::RenderContext;
 use mdbook::MDBook;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io;
 use std::io::Write;
@@ -13,8 +16,14 @@ use std::path::Path;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
+#[cfg(feature = "latex")]
+use pulldown_cmark::{Event, Parser};
+#[cfg(feature = "latex")]
+use std::cell::RefCell;
 #[cfg(feature = "latex")]
 use std::fmt::Write as FmtWrite;
+#[cfg(feature = "latex")]
+use walkdir::WalkDir;
 
 #[cfg(feature = "pdf")]
 use tectonic::status::{plain::PlainStatusBackend, ChatterLevel};
@@ -39,6 +48,316 @@ pub struct Config {
     pub latex: bool,
     pub pdf: bool,
     pub custom_template: Option<String>,
+    pub preamble: Option<String>,
+    /// Output file name template, see [`output_filename`].
+    pub output_filename: Option<String>,
+    /// Subdirectory of the build destination to write output into.
+    pub output_dir: Option<String>,
+    /// Book version substituted for `{version}` in file names.
+    pub version: Option<String>,
+    /// Globs of chapter paths (relative to `src`) to render; empty renders all.
+    pub include: Vec<String>,
+    /// Globs of chapter paths to leave out of the PDF.
+    pub exclude: Vec<String>,
+    /// Globs of source files not listed in `SUMMARY.md` to append to the PDF only.
+    pub pdf_only: Vec<String>,
+    /// Output the combined book (default `true`).
+    pub book: Option<bool>,
+    /// Also output one document per chapter.
+    pub per_chapter: bool,
+    /// File name template for per-chapter output, also supporting `{chapter}`.
+    pub chapter_filename: Option<String>,
+    /// URL of the online book, used for intra-book links in per-chapter output.
+    pub online_url: Option<String>,
+    /// Place prefix, numbered and suffix chapters in `\frontmatter`, `\mainmatter`
+    /// and `\backmatter` (or `\appendix`).
+    pub book_matter: bool,
+    /// How suffix chapters are typeset when `book-matter` is enabled.
+    pub suffix_chapters: SuffixChapters,
+    /// Extra admonition kinds (or overridden colours) as kind = "RRGGBB".
+    pub admonitions: BTreeMap<String, String>,
+    /// Markdown extensions to enable, replacing the default set.
+    pub extensions: Option<Vec<md2tex::Extension>>,
+    /// How Markdown tables are drawn: `grid`, `booktabs` or `zebra`.
+    pub table_style: md2tex::TableStyle,
+    /// Build an index from `<!-- index: term -->` marks, which the HTML book hides.
+    pub index: bool,
+    /// Also index inline code identifiers.
+    pub index_code: bool,
+    /// Leave sorting the index to `makeindex`, for LaTeX output compiled elsewhere.
+    /// Tectonic can't run it, so by default the index is sorted by the renderer.
+    pub makeindex: bool,
+    /// Glossary file relative to the book root, either TOML (`term = "description"`, or
+    /// a table with `long` and `description` for acronyms) or a Markdown definition list.
+    pub glossary: Option<String>,
+    /// Title of the glossary appendix.
+    pub glossary_title: Option<String>,
+    /// BibTeX (`.bib`) or CSL-JSON (`.json`) file relative to the book root,
+    /// which `[@key]` citations are resolved against.
+    pub bibliography: Option<String>,
+    /// Whether references are listed at the end of the `book` or of each `chapter`.
+    pub bibliography_placement: BibliographyPlacement,
+    /// Title of the references section.
+    pub references_title: Option<String>,
+    /// Add a list of figures after the table of contents.
+    pub list_of_figures: bool,
+    /// Add a list of tables after the table of contents.
+    pub list_of_tables: bool,
+    /// Add a list of captioned code listings after the table of contents.
+    pub list_of_listings: bool,
+    /// Extra diagram languages (or overridden commands) as language = "command", where
+    /// the command reads the source on stdin and writes SVG to stdout, or takes `{input}`
+    /// and `{output}` file arguments. An empty command keeps the language as code.
+    pub diagrams: BTreeMap<String, String>,
+    /// Directory rendered diagrams are cached in, relative to the book root
+    /// (by default `diagrams` in the output directory).
+    pub diagram_cache: Option<String>,
+    /// Directory of emoji SVGs relative to the book root, named by code points like
+    /// Twemoji, OpenMoji or Noto Emoji, which are drawn as inline images.
+    pub emoji_images: Option<String>,
+    /// Font for emoji without an image (by default `Noto Emoji`).
+    pub emoji_font: Option<String>,
+    /// Main polyglossia language, by default derived from `book.language`.
+    pub language: Option<String>,
+    /// Polyglossia languages to load besides those of the scripts found in the book.
+    pub other_languages: Vec<String>,
+    /// LaTeX replacements for Unicode characters, as character = "LaTeX".
+    pub unicode: BTreeMap<String, String>,
+    pub variant: Vec<Variant>,
+}
+
+/// Division holding suffix chapters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SuffixChapters {
+    /// Unnumbered chapters after `\backmatter`.
+    #[default]
+    Backmatter,
+    /// Lettered chapters after `\appendix`.
+    Appendix,
+}
+
+/// A named output built from the shared chapter LaTeX (`[[output.latex-pdf.variant]]`).
+///
+/// Unset fields fall back to the top-level `output.latex-pdf` values.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Variant {
+    pub name: Option<String>,
+    pub latex: Option<bool>,
+    pub pdf: Option<bool>,
+    pub custom_template: Option<String>,
+    pub preamble: Option<String>,
+    pub filename: Option<String>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub book: Option<bool>,
+    pub per_chapter: Option<bool>,
+    pub chapter_filename: Option<String>,
+}
+
+/// Where the references section goes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BibliographyPlacement {
+    #[default]
+    Book,
+    Chapter,
+}
+
+/// A term in a TOML glossary file.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum GlossaryValue {
+    Description(String),
+    Entry {
+        long: Option<String>,
+        #[serde(default)]
+        description: String,
+    },
+}
+
+impl Config {
+    /// Built-in admonition kinds merged with the configured ones.
+    pub fn admonition_colors(
+        &self,
+    ) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+        let mut colors: BTreeMap<String, String> = md2tex::ADMONITION_COLORS
+            .iter()
+            .map(|(kind, color)| (kind.to_string(), color.to_string()))
+            .collect();
+
+        for (kind, color) in &self.admonitions {
+            let hex = color.trim_start_matches('#');
+            if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!(
+                    "admonition colour `{}` of `{}` isn't a hex colour like `448AFF`",
+                    color, kind
+                )
+                .into());
+            }
+            colors.insert(kind.to_lowercase(), hex.to_string());
+        }
+
+        Ok(colors)
+    }
+
+    /// Configured Unicode rules, whose keys must be single characters.
+    pub fn unicode_rules(&self) -> Result<BTreeMap<char, String>, Box<dyn std::error::Error>> {
+        self.unicode
+            .iter()
+            .map(|(key, latex)| {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Ok((c, latex.clone())),
+                    _ => Err(format!("Unicode rule `{}` isn't a single character", key).into()),
+                }
+            })
+            .collect()
+    }
+
+    /// Built-in diagram commands merged with the configured ones.
+    pub fn diagram_commands(&self) -> BTreeMap<String, String> {
+        let mut commands: BTreeMap<String, String> = md2tex::DIAGRAM_COMMANDS
+            .iter()
+            .map(|(lang, command)| (lang.to_string(), command.to_string()))
+            .collect();
+
+        commands.extend(self.diagrams.clone());
+        commands
+    }
+
+    /// Main polyglossia language of the book.
+    pub fn language(&self, book: &BookConfig) -> String {
+        match (&self.language, &book.language) {
+            (Some(language), _) => language.clone(),
+            (None, Some(code)) => md2tex::polyglossia_language(code).to_string(),
+            (None, None) => "english".to_string(),
+        }
+    }
+
+    /// Entries of the configured glossary file.
+    pub fn glossary(
+        &self,
+        root: &Path,
+    ) -> Result<Vec<md2tex::GlossaryEntry>, Box<dyn std::error::Error>> {
+        let path = match self.glossary {
+            Some(ref path) => root.join(path),
+            None => return Ok(Vec::new()),
+        };
+        let source = std::fs::read_to_string(&path)?;
+
+        if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+            return Ok(md2tex::parse_glossary(&source));
+        }
+
+        let terms: BTreeMap<String, GlossaryValue> = toml::from_str(&source)?;
+        Ok(terms
+            .into_iter()
+            .map(|(term, value)| match value {
+                GlossaryValue::Description(description) => md2tex::GlossaryEntry {
+                    term,
+                    long: None,
+                    description,
+                },
+                GlossaryValue::Entry { long, description } => md2tex::GlossaryEntry {
+                    term,
+                    long,
+                    description,
+                },
+            })
+            .collect())
+    }
+
+    /// The configured bibliography, with its placement.
+    pub fn bibliography(
+        &self,
+        root: &Path,
+    ) -> Result<Option<md2tex::Bibliography>, Box<dyn std::error::Error>> {
+        let path = match self.bibliography {
+            Some(ref path) => root.join(path),
+            None => return Ok(None),
+        };
+        let source = std::fs::read_to_string(&path)?;
+
+        let bibliography = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => md2tex::Bibliography::from_csl_json(&source)?,
+            _ => md2tex::Bibliography::from_bibtex(&source),
+        };
+
+        Ok(Some(bibliography.per_chapter(
+            self.bibliography_placement == BibliographyPlacement::Chapter,
+        )))
+    }
+
+    /// Variants to build, with top-level values filled in.
+    ///
+    /// A book without `variant` entries builds a single unnamed variant.
+    pub fn variants(&self) -> Result<Vec<Variant>, Box<dyn std::error::Error>> {
+        let defaults = Variant {
+            name: None,
+            latex: Some(self.latex),
+            pdf: Some(self.pdf),
+            custom_template: self.custom_template.clone(),
+            preamble: self.preamble.clone(),
+            filename: self.output_filename.clone(),
+            include: Some(self.include.clone()),
+            exclude: Some(self.exclude.clone()),
+            book: Some(self.book.unwrap_or(true)),
+            per_chapter: Some(self.per_chapter),
+            chapter_filename: self.chapter_filename.clone(),
+        };
+
+        if self.variant.is_empty() {
+            return Ok(vec![defaults]);
+        }
+
+        // Variants sharing a name would write the same files.
+        for (i, v) in self.variant.iter().enumerate() {
+            if self.variant[..i].iter().any(|other| other.name == v.name) {
+                return Err(match &v.name {
+                    Some(name) => format!("variant `{}` is defined more than once", name),
+                    None => "more than one variant has no name".to_string(),
+                }
+                .into());
+            }
+        }
+
+        // Top-level file names are shared, they need the variant name to tell its files apart.
+        let inherited = |v: &Variant, template: &Option<String>| match &v.name {
+            Some(_) => template
+                .as_deref()
+                .map(|t| with_placeholder(t, "{variant}")),
+            None => template.clone(),
+        };
+
+        Ok(self
+            .variant
+            .iter()
+            .map(|v| Variant {
+                name: v.name.clone(),
+                latex: v.latex.or(defaults.latex),
+                pdf: v.pdf.or(defaults.pdf),
+                custom_template: v
+                    .custom_template
+                    .clone()
+                    .or_else(|| defaults.custom_template.clone()),
+                preamble: v.preamble.clone().or_else(|| defaults.preamble.clone()),
+                filename: v
+                    .filename
+                    .clone()
+                    .or_else(|| inherited(v, &defaults.filename)),
+                include: v.include.clone().or_else(|| defaults.include.clone()),
+                exclude: v.exclude.clone().or_else(|| defaults.exclude.clone()),
+                book: v.book.or(defaults.book),
+                per_chapter: v.per_chapter.or(defaults.per_chapter),
+                chapter_filename: v
+                    .chapter_filename
+                    .clone()
+                    .or_else(|| inherited(v, &defaults.chapter_filename)),
+            })
+            .collect())
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -71,46 +390,318 @@ fn build(ctx: &RenderContext) -> Result<(), Box<dyn std::error::Error>> {
         let title = ctx.config.book.title.as_ref().unwrap();
         let authors = ctx.config.book.authors.join(" \\and ");
 
-        // Copy template data into memory.
-        let mut template = if let Some(custom_template) = &cfg.custom_template {
-            let mut custom_template_path = ctx.root.clone();
-            custom_template_path.push(custom_template);
-            std::fs::read_to_string(custom_template_path)?
-        } else {
-            include_str!("template.tex").to_string()
+        let variants = cfg.variants()?;
+
+        let glossary = cfg.glossary(&ctx.root)?;
+        let bibliography = cfg.bibliography(&ctx.root)?;
+
+        // Chapters are converted once and shared by every variant.
+        let mut chapters = Vec::new();
+        if variants
+            .iter()
+            .any(|v| v.latex.unwrap_or_default() || v.pdf.unwrap_or_default())
+        {
+            // List options need enumitem in every template the chapters go into.
+            let mut enumitem = true;
+            for variant in &variants {
+                enumitem &= read_template(ctx, variant)?.contains("{enumitem}");
+            }
+            chapters = get_latex(ctx, &cfg, &glossary, bibliography.as_ref(), enumitem)?;
+        }
+
+        let dest = match cfg.output_dir {
+            Some(ref dir) => ctx.destination.join(dir),
+            None => ctx.destination.clone(),
         };
+        std::fs::create_dir_all(&dest)?;
+
+        for variant in &variants {
+            let selected = select_chapters(&chapters, variant)?;
+
+            if variant.book.unwrap_or(true) {
+                let mut template = load_template(ctx, &cfg, variant, title, &authors)?;
+
+                let mut content = String::new();
+                let mut matter = None;
+                for chapter in &selected {
+                    // Switch divisions where the SUMMARY.md structure changes.
+                    if cfg.book_matter && matter != Some(chapter.matter) {
+                        match chapter.matter {
+                            Matter::Front => {}
+                            Matter::Main => content.push_str("\\mainmatter\n"),
+                            Matter::Back => match cfg.suffix_chapters {
+                                SuffixChapters::Backmatter => content.push_str("\\backmatter\n"),
+                                SuffixChapters::Appendix => content.push_str("\\appendix\n"),
+                            },
+                        }
+                        matter = Some(chapter.matter);
+                    }
+
+                    writeln!(content, "{}", chapter.latex).unwrap();
+                }
+
+                if let (Some(bibliography), BibliographyPlacement::Book) =
+                    (&bibliography, cfg.bibliography_placement)
+                {
+                    let keys: Vec<&str> = selected
+                        .iter()
+                        .flat_map(|c| &c.citations)
+                        .map(String::as_str)
+                        .collect();
+                    let title = cfg.references_title.as_deref().unwrap_or("References");
+                    content.push_str(&bibliography.print_references(&keys, title, 0, None));
+                }
+
+                if !glossary.is_empty() {
+                    let title = cfg.glossary_title.as_deref().unwrap_or("Glossary");
+                    content.push_str(&md2tex::print_glossary(&glossary, title));
+                }
 
-        // Add title and author information.
-        template = template
-            .replace(r"\title{}", &format!("\\title{{{}}}", title))
-            .replace(r"\author{}", &format!("\\author{{{}}}", authors));
+                if cfg.index && cfg.makeindex {
+                    content.push_str("\\printindex\n");
+                } else if cfg.index {
+                    let entries: Vec<_> = selected.iter().flat_map(|c| &c.index).collect();
+                    if !entries.is_empty() {
+                        content.push_str(&md2tex::print_index(&entries));
+                    }
+                }
 
-        let mut latex = String::new();
-        if cfg.latex || cfg.pdf {
-            latex = get_latex(ctx, &cfg, &template);
+                // Title page and table of contents belong to the front matter too.
+                if cfg.book_matter {
+                    let mark = "\\begin{document}";
+                    let pos = template.find(mark).unwrap() + mark.len();
+                    template.insert_str(pos, "\n\\frontmatter");
+                }
+
+                let latex = fill_template(&template, &content, &cfg);
+                write_outputs(latex, variant, |ext| {
+                    output_filename(&dest, &ctx.config, &cfg, variant, None, ext)
+                })?;
+            }
+
+            if variant.per_chapter.unwrap_or_default() {
+                for chapter in &selected {
+                    // The chapter name takes the place of the book title on the title page.
+                    let title = md2tex::escape(&chapter.name, md2tex::EscapeMode::Text);
+                    let template = load_template(ctx, &cfg, variant, &title, &authors)?;
+                    let latex = fill_template(
+                        &template,
+                        chapter.standalone.as_deref().unwrap_or(&chapter.latex),
+                        &cfg,
+                    );
+
+                    // Chapters whose names give the same file name are told apart by their path.
+                    let mut name = chapter.name.clone();
+                    let slug = slugify(&chapter.name);
+                    if selected.iter().filter(|c| slugify(&c.name) == slug).count() > 1 {
+                        if let Some(path) = &chapter.path {
+                            write!(name, " {}", path.with_extension("").display()).unwrap();
+                        }
+                    }
+                    write_outputs(latex, variant, |ext| {
+                        output_filename(&dest, &ctx.config, &cfg, variant, Some(&name), ext)
+                    })?;
+                }
+            }
         }
+    }
+
+    Ok(())
+}
+
+/// Reads the variant's custom template, or the built-in one.
+#[cfg(feature = "latex")]
+fn read_template(
+    ctx: &RenderContext,
+    variant: &Variant,
+) -> Result<String, Box<dyn std::error::Error>> {
+    // Copy template data into memory.
+    Ok(if let Some(custom_template) = &variant.custom_template {
+        let mut custom_template_path = ctx.root.clone();
+        custom_template_path.push(custom_template);
+        std::fs::read_to_string(custom_template_path)?
+    } else {
+        include_str!("template.tex").to_string()
+    })
+}
+
+/// Reads the variant's template and fills in title, authors, admonition colours and preamble.
+#[cfg(feature = "latex")]
+fn load_template(
+    ctx: &RenderContext,
+    cfg: &Config,
+    variant: &Variant,
+    title: &str,
+    authors: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut template = read_template(ctx, variant)?;
+
+    // Add title and author information.
+    template = template
+        .replace(r"\title{}", &format!("\\title{{{}}}", title))
+        .replace(r"\author{}", &format!("\\author{{{}}}", authors));
+
+    // Lists of numbered floats, behind the table of contents.
+    let lists: String = [
+        (cfg.list_of_figures, r"\listoffigures"),
+        (cfg.list_of_tables, r"\listoftables"),
+        (cfg.list_of_listings, r"\listoflistings"),
+    ]
+    .iter()
+    .filter(|(enabled, _)| *enabled)
+    .map(|(_, command)| format!("\n{}", command))
+    .collect();
+    template = template.replacen(
+        r"\tableofcontents",
+        &format!(r"\tableofcontents{}", lists),
+        1,
+    );
+
+    let mut preamble = String::new();
 
-        // Output latex file.
-        if cfg.latex {
-            let filename = output_filename(&ctx.destination, &ctx.config, "tex");
-            write_file(&latex, filename);
+    // Colours used by the template's admonition environment, if it has one.
+    // Otherwise admonitions are plain quotes headed by their title.
+    let colors = match template.contains("{admonition}") {
+        true => cfg.admonition_colors()?,
+        false => {
+            preamble.push_str(
+                "\\newenvironment{admonition}[2]\n  {\\begin{quote}\\textbf{#2}\\par}\n  {\\end{quote}}\n",
+            );
+            BTreeMap::new()
         }
+    };
+    for (kind, color) in colors {
+        writeln!(
+            preamble,
+            "\\definecolor{{admonition-{}}}{{HTML}}{{{}}}",
+            kind, color
+        )
+        .unwrap();
+    }
+    preamble.push('\n');
 
-        #[cfg(feature = "pdf")]
-        {
-            // Output PDF file.
-            if cfg.pdf {
-                let filename = output_filename(&ctx.destination, &ctx.config, "pdf");
-                write_pdf(latex, filename);
+    if cfg.index && cfg.makeindex {
+        preamble.push_str("\\usepackage[intoc]{imakeidx}\n\\makeindex\n");
+    } else if cfg.index {
+        // The generated index compares the pages of its references.
+        preamble.push_str("\\usepackage{refcount}\n");
+    }
+
+    // Languages for polyglossia, if the template uses it rather than babel.
+    if template.contains("{polyglossia}") {
+        let language = cfg.language(&ctx.config.book);
+        writeln!(preamble, "\\setmainlanguage{{{}}}", language).unwrap();
+
+        // An explicit `book.text-direction` wins over the language's own.
+        let rtl = match ctx.config.book.text_direction {
+            Some(direction) => direction == TextDirection::RightToLeft,
+            None => md2tex::is_rtl(&language),
+        };
+        if rtl != md2tex::is_rtl(&language) {
+            // Polyglossia only loads bidi, which defines `\setRTL`, for RTL languages.
+            if rtl {
+                preamble.push_str("\\usepackage{bidi}\n");
             }
+            let direction = if rtl { "RTL" } else { "LTR" };
+            writeln!(preamble, "\\AtBeginDocument{{\\set{}}}", direction).unwrap();
+        }
+        preamble.push('\n');
+    }
+
+    // Configured Unicode rules, next to the template's own, for code as well.
+    for (c, latex) in cfg.unicode_rules()? {
+        writeln!(preamble, "\\newunicodechar{{{}}}{{{}}}", c, latex).unwrap();
+    }
+
+    // Add variant specific preamble.
+    if let Some(extra) = &variant.preamble {
+        writeln!(preamble, "{}\n", extra).unwrap();
+    }
+
+    let mark = "\\begin{document}";
+    let pos = template.find(mark).unwrap();
+    template.insert_str(pos, &preamble);
+
+    Ok(template)
+}
+
+/// Writes the `.tex` and/or `.pdf` enabled for the variant.
+#[cfg(feature = "latex")]
+fn write_outputs(
+    latex: String,
+    variant: &Variant,
+    filename: impl Fn(&str) -> Result<PathBuf, Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Output latex file.
+    if variant.latex.unwrap_or_default() {
+        write_file(&latex, filename("tex")?);
+    }
+
+    #[cfg(feature = "pdf")]
+    {
+        // Output PDF file.
+        if variant.pdf.unwrap_or_default() {
+            write_pdf(latex, filename("pdf")?);
         }
     }
 
     Ok(())
 }
 
+/// LaTeX of a single chapter, kept apart so each variant can pick its own subset.
+#[cfg(feature = "latex")]
+struct ConvertedChapter {
+    /// Source path relative to `src`.
+    path: Option<PathBuf>,
+    name: String,
+    latex: String,
+    /// Conversion for per-chapter output, with intra-book links pointing to the online book.
+    standalone: Option<String>,
+    matter: Matter,
+    /// Index terms marked in the chapter.
+    index: Vec<md2tex::IndexEntry>,
+    /// Keys of the references cited in the chapter.
+    citations: Vec<String>,
+}
+
+/// A chapter's LaTeX, its per-chapter variant, its index terms and its citations.
 #[cfg(feature = "latex")]
-fn get_latex(ctx: &RenderContext, cfg: &Config, template: &String) -> String {
+type Converted = (String, Option<String>, Vec<md2tex::IndexEntry>, Vec<String>);
+
+/// Converts a chapter from its content, path, section number and chapter number.
+#[cfg(feature = "latex")]
+type Convert<'a> = dyn Fn(&str, &Path, Option<&[u32]>, i32) -> Converted + 'a;
+
+/// Book division a chapter belongs to, following mdBook's prefix, numbered and suffix chapters.
+#[cfg(feature = "latex")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Matter {
+    Front,
+    Main,
+    Back,
+}
+
+/// Marks a chapter that is only meant for the HTML book.
+#[cfg(feature = "latex")]
+const HTML_ONLY_MARK: &str = "<!-- latex-pdf: html-only -->";
+
+/// Whether a chapter has the [`HTML_ONLY_MARK`] comment on a line of its own, outside code.
+#[cfg(feature = "latex")]
+fn is_html_only(content: &str) -> bool {
+    // Block HTML comes in whole lines, an inline comment is only the comment.
+    Parser::new(content).any(|event| {
+        matches!(event, Event::Html(html) if html.ends_with('\n') && html.trim() == HTML_ONLY_MARK)
+    })
+}
+
+#[cfg(feature = "latex")]
+fn get_latex(
+    ctx: &RenderContext,
+    cfg: &Config,
+    glossary: &[md2tex::GlossaryEntry],
+    bibliography: Option<&md2tex::Bibliography>,
+    enumitem: bool,
+) -> Result<Vec<ConvertedChapter>, Box<dyn std::error::Error>> {
     let asset_prefix = ctx
         .destination
         .strip_prefix(&ctx.root)
@@ -118,53 +709,284 @@ fn get_latex(ctx: &RenderContext, cfg: &Config, template: &String) -> String {
         .unwrap_or_else(|_| ctx.root.clone())
         .join(ctx.config.book.src.as_path());
 
-    let mut output_template = template.clone();
+    let admonitions = cfg.admonition_colors()?;
+    let unicode = cfg.unicode_rules()?;
+    let emoji_images = cfg.emoji_images.as_ref().map(|dir| ctx.root.join(dir));
+    let language = cfg.language(&ctx.config.book);
+    let diagrams = md2tex::Diagrams::new(
+        cfg.diagram_commands(),
+        match cfg.diagram_cache {
+            Some(ref cache) => ctx.root.join(cache),
+            None => ctx.destination.join("diagrams"),
+        },
+    );
+    let references_title = cfg.references_title.as_deref().unwrap_or("References");
+
+    let convert = |content: &str, path: &Path, number: Option<&[u32]>, chapter_number: i32| {
+        let prefix = asset_prefix.join(path.parent().unwrap());
+        let chapter_path = path
+            .iter()
+            .map(|c| c.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let index = RefCell::new(Vec::new());
+        let citations = RefCell::new(Vec::new());
+
+        // Index and glossary only exist in the whole book.
+        let run = |link_base: Option<&str>, book: bool| {
+            let mut converter = md2tex::Converter::new(content)
+                .dest(&ctx.destination)
+                .assets(&prefix)
+                .chapter_level_offset(chapter_number)
+                .admonitions(&admonitions)
+                .diagrams(&diagrams)
+                .language(&language)
+                .table_style(cfg.table_style)
+                .unicode(&unicode)
+                .enumitem(enumitem)
+                .chapter_path(&chapter_path);
+            if let Some(ref extensions) = cfg.extensions {
+                converter = converter.extensions(extensions);
+            }
+            if let Some(number) = number {
+                converter = converter.section_number(number);
+            }
+            if let Some(link_base) = link_base {
+                converter = converter.link_base(link_base);
+            }
+            if let Some(ref images) = emoji_images {
+                converter = converter.emoji_images(images);
+            }
+            if book && cfg.index {
+                converter = converter.index(&index).index_code(cfg.index_code);
+            }
+            if book {
+                converter = converter.glossary(glossary);
+            }
+            if let Some(bibliography) = bibliography {
+                converter = converter.bibliography(bibliography, &citations);
+            }
+            converter.run()
+        };
+
+        let mut latex = run(None, true);
+
+        let cited = citations.take();
+        let references = bibliography.map(|bibliography| {
+            let keys: Vec<&str> = cited.iter().map(String::as_str).collect();
+            // One level below the chapter's own heading, as sub-chapters start deeper.
+            let depth = number.map_or(1, |n| n.len().max(1));
+            bibliography.print_references(&keys, references_title, depth, Some(&chapter_path))
+        });
+        if cfg.bibliography_placement == BibliographyPlacement::Chapter {
+            latex.push_str(references.as_deref().unwrap_or_default());
+        }
+
+        // Intra-book links can't be resolved inside a single chapter,
+        // so point them at the online book instead.
+        let standalone =
+            if cfg.per_chapter || cfg.variant.iter().any(|v| v.per_chapter == Some(true)) {
+                let link_base = cfg.online_url.as_ref().map(|url| {
+                    let mut base = url.trim_end_matches('/').to_string();
+                    for component in path.parent().unwrap().iter() {
+                        write!(base, "/{}", component.to_string_lossy()).unwrap();
+                    }
+                    base.push('/');
+                    base
+                });
+
+                // A single chapter always lists its own references.
+                let mut standalone = run(link_base.as_deref(), false);
+                standalone.push_str(references.as_deref().unwrap_or_default());
+                Some(standalone)
+            } else {
+                None
+            };
+
+        (latex, standalone, index.into_inner(), cited)
+    };
 
     // Iterate through markdown source.
-    let mut content = String::new();
+    let mut chapters = Vec::new();
 
     fn for_each_chap(
-        content: &mut String,
-        asset_prefix: &Path,
-        dest_prefix: &Path,
+        chapters: &mut Vec<ConvertedChapter>,
+        convert: &Convert,
         cfg: &Config,
+        parent: Option<Matter>,
         items: &[BookItem],
     ) {
+        let mut seen_numbered = false;
+
         for item in items {
             if let BookItem::Chapter(ref ch) = *item {
-                let prefix = asset_prefix.join(ch.path.as_ref().and_then(|p| p.parent()).unwrap());
+                // Top-level unnumbered chapters before the first numbered one are prefix
+                // chapters, those after it are suffix chapters.
+                let matter = parent.unwrap_or_else(|| {
+                    if ch.number.is_some() {
+                        seen_numbered = true;
+                        Matter::Main
+                    } else if seen_numbered {
+                        Matter::Back
+                    } else {
+                        Matter::Front
+                    }
+                });
 
-                let chapter_number: i32 = if let Some(number) = &ch.number {
-                    number.0[0] as i32
-                } else {
-                    0
-                };
+                if !is_html_only(&ch.content) {
+                    let chapter_number: i32 = if let Some(number) = &ch.number {
+                        number.0[0] as i32
+                    } else if cfg.book_matter
+                        && matter == Matter::Back
+                        && cfg.suffix_chapters == SuffixChapters::Appendix
+                    {
+                        // Appendices are lettered by LaTeX.
+                        1
+                    } else {
+                        0
+                    };
 
-                let latex = md2tex::Converter::new(&ch.content)
-                    .dest(dest_prefix)
-                    .assets(&prefix)
-                    .chapter_level_offset(chapter_number)
-                    .run();
+                    let (latex, standalone, index, citations) = convert(
+                        &ch.content,
+                        ch.path.as_ref().unwrap(),
+                        ch.number.as_ref().map(|n| n.0.as_slice()),
+                        chapter_number,
+                    );
 
-                writeln!(content, "{}", latex).unwrap();
+                    chapters.push(ConvertedChapter {
+                        path: ch.path.clone(),
+                        name: ch.name.clone(),
+                        latex,
+                        standalone,
+                        matter,
+                        index,
+                        citations,
+                    });
+                }
 
-                for_each_chap(content, asset_prefix, dest_prefix, cfg, &ch.sub_items);
+                for_each_chap(chapters, convert, cfg, Some(matter), &ch.sub_items);
             }
         }
     }
 
-    for_each_chap(
-        &mut content,
-        &asset_prefix,
-        &ctx.destination,
-        cfg,
-        &ctx.book.sections,
-    );
+    for_each_chap(&mut chapters, &convert, cfg, None, &ctx.book.sections);
+
+    // Files outside of SUMMARY.md are never rendered to HTML.
+    if !cfg.pdf_only.is_empty() {
+        let patterns = compile_globs(&cfg.pdf_only)?;
+        let src_dir = ctx.root.join(&ctx.config.book.src);
+
+        // Chapters of the book are converted already.
+        let mut in_book: Vec<&Path> = ctx
+            .book
+            .iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(ch) => ch.path.as_deref(),
+                _ => None,
+            })
+            .collect();
+        in_book.push(Path::new("SUMMARY.md"));
+
+        let mut paths: Vec<PathBuf> = WalkDir::new(&src_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| e.path().strip_prefix(&src_dir).ok().map(Path::to_path_buf))
+            .filter(|p| !in_book.contains(&p.as_path()))
+            .filter(|p| patterns.iter().any(|pat| pat.matches_path(p)))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let content = std::fs::read_to_string(src_dir.join(&path))?;
+            let (latex, standalone, index, citations) = convert(&content, &path, None, 0);
+
+            chapters.push(ConvertedChapter {
+                name: path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                path: Some(path),
+                latex,
+                standalone,
+                matter: Matter::Back,
+                index,
+                citations,
+            });
+        }
+    }
+
+    Ok(chapters)
+}
+
+/// Chapters matching a variant's `include`/`exclude` globs.
+#[cfg(feature = "latex")]
+fn select_chapters<'a>(
+    chapters: &'a [ConvertedChapter],
+    variant: &Variant,
+) -> Result<Vec<&'a ConvertedChapter>, glob::PatternError> {
+    let include = compile_globs(variant.include.as_deref().unwrap_or_default())?;
+    let exclude = compile_globs(variant.exclude.as_deref().unwrap_or_default())?;
+
+    Ok(chapters
+        .iter()
+        .filter(|chapter| match chapter.path {
+            Some(ref path) => {
+                (include.is_empty() || include.iter().any(|p| p.matches_path(path)))
+                    && !exclude.iter().any(|p| p.matches_path(path))
+            }
+            None => include.is_empty(),
+        })
+        .collect())
+}
+
+#[cfg(feature = "latex")]
+fn compile_globs(globs: &[String]) -> Result<Vec<glob::Pattern>, glob::PatternError> {
+    globs.iter().map(|g| glob::Pattern::new(g)).collect()
+}
+
+/// Inserts converted chapters into the template after the begin mark.
+#[cfg(feature = "latex")]
+fn fill_template(template: &str, content: &str, cfg: &Config) -> String {
+    let mut output_template = template.to_string();
+    let mut preamble = String::new();
+
+    // Languages of the other scripts in the content, and those configured, for polyglossia.
+    if template.contains("{polyglossia}") {
+        let mut languages = md2tex::used_languages(content);
+        for language in &cfg.other_languages {
+            if !languages.contains(&language.as_str()) {
+                languages.push(language);
+            }
+        }
+        if !languages.is_empty() {
+            writeln!(preamble, "\\setotherlanguages{{{}}}\n", languages.join(",")).unwrap();
+        }
+    }
+
+    // Emoji, either as images or in their own font, only for content that has any.
+    if content.contains("\\emoji") {
+        writeln!(
+            preamble,
+            "\\newfontfamily\\emojifont{{{}}}",
+            cfg.emoji_font.as_deref().unwrap_or("Noto Emoji")
+        )
+        .unwrap();
+        preamble.push_str(concat!(
+            "\\providecommand{\\emoji}[1]{\\texorpdfstring{{\\emojifont #1}}{#1}}\n",
+            "\\providecommand{\\emojiimage}[2]{\\texorpdfstring",
+            "{\\raisebox{-0.15em}{\\includegraphics[height=1em]{#1}}}{#2}}\n\n",
+        ));
+    }
+
+    let pos = output_template.find("\\begin{document}").unwrap();
+    output_template.insert_str(pos, &preamble);
 
     let begin = "mdbook-latex-pdf begin";
-    let target = output_template.find(&begin).unwrap() + begin.len();
+    let target = output_template.find(begin).unwrap() + begin.len();
 
-    output_template.insert_str(target, &content);
+    output_template.insert_str(target, content);
     output_template
 }
 
@@ -235,18 +1057,167 @@ fn write_file(data: &str, filename: PathBuf) {
     let display = filename.display();
 
     let mut file = match File::create(&filename) {
-        Err(why) => panic!("Couldn't create {}: {}", display, why.to_string()),
+        Err(why) => panic!("Couldn't create {}: {}", display, why),
         Ok(file) => file,
     };
 
     if let Err(why) = file.write_all(data.as_bytes()) {
-        panic!("Couldn't write to {}: {}", display, why.to_string())
+        panic!("Couldn't write to {}: {}", display, why)
+    }
+}
+
+/// Builds the output path for a variant.
+///
+/// The file name comes from the variant's `filename` (or `output-filename`) template,
+/// defaulting to `{title}` or `{title}-{variant}`. Per-chapter output uses `chapter-filename`
+/// and `{title}-{chapter}` instead. Supported placeholders are `{title}`, `{version}`,
+/// `{date}`, `{variant}` and `{chapter}`; substituted values are slugified. A chapter
+/// template without `{chapter}` gets `-{chapter}` appended.
+fn output_filename(
+    dest: &Path,
+    config: &MdConfig,
+    cfg: &Config,
+    variant: &Variant,
+    chapter: Option<&str>,
+    extension: &str,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let template = match (chapter, &variant.filename, &variant.name) {
+        (Some(_), _, _) => match (&variant.chapter_filename, &variant.name) {
+            (Some(filename), _) => filename.as_str(),
+            (None, Some(_)) => "{title}-{variant}-{chapter}",
+            (None, None) => "{title}-{chapter}",
+        },
+        (None, Some(filename), _) => filename.as_str(),
+        (None, None, Some(_)) => "{title}-{variant}",
+        (None, None, None) => "{title}",
+    };
+
+    // Chapters would overwrite each other's files.
+    let template = match chapter {
+        Some(_) => with_placeholder(template, "{chapter}"),
+        None => template.to_string(),
+    };
+
+    let title = match slugify(config.book.title.as_deref().unwrap_or_default()) {
+        title if title.is_empty() => "book".to_string(),
+        title => title,
+    };
+
+    let filename = template
+        .replace("{title}", &title)
+        .replace("{version}", &slugify(cfg.version.as_deref().unwrap_or("")))
+        .replace("{date}", &today())
+        .replace("{variant}", &slugify(variant.name.as_deref().unwrap_or("")))
+        .replace("{chapter}", &slugify(chapter.unwrap_or("")));
+
+    let path = dest.join(filename);
+
+    // Templates may point into subdirectories.
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Couldn't create {}: {}", parent.display(), e))?;
     }
+
+    // Append rather than `with_extension`, which would eat anything after a dot.
+    let mut path = path.into_os_string();
+    path.push(".");
+    path.push(extension);
+    Ok(PathBuf::from(path))
 }
 
-fn output_filename(dest: &Path, config: &MdConfig, extension: &str) -> PathBuf {
-    match config.book.title {
-        Some(ref title) => dest.join(title).with_extension(extension),
-        None => dest.join("book").with_extension(extension),
+/// Appends `-{placeholder}` to a file name template that doesn't use it.
+fn with_placeholder(template: &str, placeholder: &str) -> String {
+    match template.contains(placeholder) {
+        true => template.to_string(),
+        false => format!("{}-{}", template, placeholder),
+    }
+}
+
+/// Makes a string safe to use as a file name.
+///
+/// Letters and digits (including CJK) are kept, everything else becomes `-`.
+fn slugify(s: &str) -> String {
+    let mut slug = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if c.is_alphanumeric() || c == '_' {
+            slug.push(c);
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.trim_matches('-').to_string()
+}
+
+/// Current UTC date as `YYYY-MM-DD`.
+fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs_keep_letters_and_digits() {
+        assert_eq!(slugify("My Book: 2nd ed."), "My-Book-2nd-ed");
+        assert_eq!(slugify("Rust 程序设计"), "Rust-程序设计");
+        assert_eq!(slugify("?!"), "");
+    }
+
+    #[test]
+    fn file_names_tell_variants_and_chapters_apart() {
+        let dest = std::env::temp_dir();
+        let mut config = MdConfig::default();
+        config.book.title = Some("?!".to_string());
+        let cfg = Config {
+            output_filename: Some("{title}".to_string()),
+            variant: vec![
+                Variant {
+                    name: Some("screen".to_string()),
+                    ..Variant::default()
+                },
+                Variant {
+                    name: Some("print".to_string()),
+                    ..Variant::default()
+                },
+            ],
+            ..Config::default()
+        };
+
+        let names: Vec<PathBuf> = cfg
+            .variants()
+            .unwrap()
+            .iter()
+            .map(|v| output_filename(&dest, &config, &cfg, v, None, "pdf").unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [dest.join("book-screen.pdf"), dest.join("book-print.pdf")]
+        );
+
+        let variant = Variant {
+            chapter_filename: Some("{title}".to_string()),
+            ..Variant::default()
+        };
+        let chapter = output_filename(&dest, &config, &cfg, &variant, Some("intro.md"), "tex");
+        assert_eq!(chapter.unwrap(), dest.join("book-intro-md.tex"));
     }
 }
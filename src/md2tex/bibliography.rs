@@ -0,0 +1,554 @@
+use super::escape::{escape, EscapeMode};
+use super::writer::TexWriter;
+use super::{label, SECTIONING};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// `[@key]`, `[see @key, p. 3; -@other]` citations.
+pub(crate) const CITATION: &str = r"\[(?P<cite>[^\[\]]*-?@[^\[\]]+)\]";
+
+/// A bibliography entry, with fields already in LaTeX.
+#[derive(Debug, Clone, Default)]
+pub struct Reference {
+    /// `(family, given)` names.
+    pub authors: Vec<(String, String)>,
+    pub year: Option<String>,
+    pub title: Option<String>,
+    /// Journal, proceedings or book the entry appeared in.
+    pub container: Option<String>,
+    pub volume: Option<String>,
+    pub number: Option<String>,
+    pub pages: Option<String>,
+    pub publisher: Option<String>,
+    pub url: Option<String>,
+    pub doi: Option<String>,
+}
+
+/// References by citation key.
+#[derive(Debug, Clone, Default)]
+pub struct Bibliography {
+    references: BTreeMap<String, Reference>,
+    /// Whether each chapter lists its own references, which makes their labels per chapter.
+    per_chapter: bool,
+}
+
+impl Bibliography {
+    /// Parses a BibTeX file, whose values are LaTeX already.
+    ///
+    /// `@string` abbreviations aren't expanded.
+    pub fn from_bibtex(source: &str) -> Bibliography {
+        let mut references = BTreeMap::new();
+        let mut rest = source;
+
+        while let Some(at) = rest.find('@') {
+            rest = &rest[at + 1..];
+
+            let open = match rest.find(['{', '(']) {
+                Some(open) => open,
+                None => break,
+            };
+            let kind = rest[..open].trim().to_lowercase();
+
+            // Their bodies may contain `@` too.
+            if matches!(kind.as_str(), "comment" | "string" | "preamble") {
+                rest = skip_balanced(&rest[open..]);
+                continue;
+            }
+            rest = &rest[open + 1..];
+
+            let comma = match rest.find(',') {
+                Some(comma) => comma,
+                None => break,
+            };
+            let key = rest[..comma].trim().to_string();
+            rest = &rest[comma + 1..];
+
+            let mut fields = BTreeMap::new();
+            loop {
+                rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+                if rest.is_empty() || rest.starts_with(['}', ')']) {
+                    break;
+                }
+
+                let eq = match rest.find('=') {
+                    Some(eq) => eq,
+                    None => break,
+                };
+                let name = rest[..eq].trim().to_lowercase();
+                let (value, after) = bibtex_value(&rest[eq + 1..]);
+                fields.insert(name, value);
+                rest = after;
+            }
+
+            let mut field = |name: &str| fields.remove(name).filter(|v| !v.is_empty());
+            let reference = Reference {
+                authors: field("author")
+                    .or_else(|| field("editor"))
+                    .map(|names| bibtex_names(&names))
+                    .unwrap_or_default(),
+                year: field("year").or_else(|| field("date").map(|d| d.chars().take(4).collect())),
+                title: field("title"),
+                container: field("journal")
+                    .or_else(|| field("journaltitle"))
+                    .or_else(|| field("booktitle")),
+                volume: field("volume"),
+                number: field("number"),
+                pages: field("pages"),
+                publisher: field("publisher")
+                    .or_else(|| field("school"))
+                    .or_else(|| field("institution")),
+                url: field("url"),
+                doi: field("doi"),
+            };
+            references.insert(key, reference);
+        }
+
+        Bibliography {
+            references,
+            per_chapter: false,
+        }
+    }
+
+    /// Parses a CSL-JSON file, whose values are plain text.
+    pub fn from_csl_json(source: &str) -> Result<Bibliography, serde_json::Error> {
+        let items: Vec<Value> = serde_json::from_str(source)?;
+        let mut references = BTreeMap::new();
+
+        for item in items {
+            let text = |name: &str| {
+                item.get(name).and_then(|v| match v {
+                    Value::String(s) => Some(escape(s, EscapeMode::Text)),
+                    Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                })
+            };
+            let key = match item.get("id") {
+                Some(Value::String(id)) => id.clone(),
+                Some(Value::Number(id)) => id.to_string(),
+                _ => continue,
+            };
+
+            let authors = item
+                .get("author")
+                .or_else(|| item.get("editor"))
+                .and_then(Value::as_array)
+                .map(|names| {
+                    names
+                        .iter()
+                        .map(|name| {
+                            let part = |p: &str| {
+                                name.get(p)
+                                    .and_then(Value::as_str)
+                                    .map(|s| escape(s, EscapeMode::Text))
+                            };
+                            (
+                                part("family")
+                                    .or_else(|| part("literal"))
+                                    .unwrap_or_default(),
+                                part("given").unwrap_or_default(),
+                            )
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let year = item
+                .pointer("/issued/date-parts/0/0")
+                .map(|year| year.to_string().trim_matches('"').to_string());
+
+            let reference = Reference {
+                authors,
+                year,
+                title: text("title"),
+                container: text("container-title"),
+                volume: text("volume"),
+                number: text("issue"),
+                pages: text("page").map(|p| p.replace('-', "--")),
+                publisher: text("publisher"),
+                url: item.get("URL").and_then(Value::as_str).map(str::to_string),
+                doi: item.get("DOI").and_then(Value::as_str).map(str::to_string),
+            };
+            references.insert(key, reference);
+        }
+
+        Ok(Bibliography {
+            references,
+            per_chapter: false,
+        })
+    }
+
+    /// List references at the end of each chapter instead of the book.
+    pub fn per_chapter(mut self, per_chapter: bool) -> Self {
+        self.per_chapter = per_chapter;
+        self
+    }
+
+    /// Label of a reference in the list the chapter's citations point to.
+    fn label(&self, chapter: Option<&str>, key: &str) -> String {
+        let chapter = if self.per_chapter { chapter } else { None };
+        format!("cite:{}", label(chapter, Some(key)))
+    }
+
+    /// Writes a citation group like `[see @key, p. 3; -@other]` as "(see Knuth 1984, p. 3; 1994)",
+    /// returning the cited keys, or `None` if it isn't a citation or any key is unknown.
+    pub(crate) fn write_citation(
+        &self,
+        wr: &mut TexWriter<String>,
+        chapter: Option<&str>,
+        citation: &str,
+    ) -> Option<Vec<String>> {
+        let regex_item =
+            Regex::new(r"^\s*(?:(?P<prefix>[^@]*?)\s+)?(?P<suppress>-?)@(?P<key>[^\s,;]+)(?:\s*,\s*(?P<locator>.*?))?\s*$")
+                .unwrap();
+
+        // Brackets with other text, like an e-mail address, aren't citations at all.
+        let items = citation
+            .split(';')
+            .map(|item| {
+                let caps = regex_item.captures(item)?;
+                let key = caps["key"]
+                    .trim_end_matches(|c: char| c.is_ascii_punctuation())
+                    .to_string();
+                Some((caps, key))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        if let Some((_, key)) = items
+            .iter()
+            .find(|(_, key)| !self.references.contains_key(key))
+        {
+            eprintln!("Warning: citation key @{} isn't in the bibliography", key);
+            return None;
+        }
+
+        wr.push('(');
+        for (i, (caps, key)) in items.iter().enumerate() {
+            if i > 0 {
+                wr.push_str("; ");
+            }
+            if let Some(prefix) = caps.name("prefix") {
+                wr.escape_str(prefix.as_str()).push(' ');
+            }
+
+            let reference = &self.references[key];
+            let year = reference.year.as_deref().unwrap_or("n.d.");
+            write!(wr, r"\hyperref[{}]{{", self.label(chapter, key)).unwrap();
+            if caps["suppress"].is_empty() {
+                write!(wr, "{} {}", reference.short_authors(), year).unwrap();
+            } else {
+                wr.push_str(year);
+            }
+            wr.push('}');
+
+            if let Some(locator) = caps.name("locator").filter(|l| !l.as_str().is_empty()) {
+                wr.push_str(", ").escape_str(locator.as_str());
+            }
+        }
+        wr.push(')');
+
+        Some(items.into_iter().map(|(_, key)| key).collect())
+    }
+
+    /// A references section listing the `cited` keys by author and year.
+    ///
+    /// `depth` is that of the section the list ends, 0 for the whole book, so that it's
+    /// headed one level below it.
+    pub fn print_references(
+        &self,
+        cited: &[&str],
+        title: &str,
+        depth: usize,
+        chapter: Option<&str>,
+    ) -> String {
+        let heading = SECTIONING[depth.min(SECTIONING.len() - 1)];
+
+        let mut keys: Vec<&str> = cited
+            .iter()
+            .copied()
+            .filter(|key| self.references.contains_key(*key))
+            .collect();
+        keys.sort_by_cached_key(|key| {
+            let r = &self.references[*key];
+            (
+                r.authors.first().map(|a| a.0.to_lowercase()),
+                r.year.clone(),
+                r.title.clone(),
+            )
+        });
+        keys.dedup();
+
+        if keys.is_empty() {
+            return String::new();
+        }
+
+        let mut references = String::new();
+        writeln!(
+            references,
+            "\\{}*{{{}}}",
+            heading,
+            escape(title, EscapeMode::Text)
+        )
+        .unwrap();
+        if heading == "chapter" {
+            writeln!(
+                references,
+                "\\addcontentsline{{toc}}{{chapter}}{{{}}}",
+                escape(title, EscapeMode::Text)
+            )
+            .unwrap();
+        }
+
+        // Hanging indent, like most author-year styles.
+        references.push_str(
+            "\\begin{list}{}{\\setlength{\\leftmargin}{2em}\\setlength{\\itemindent}{-2em}}\n",
+        );
+        for key in keys {
+            writeln!(
+                references,
+                "\\item\\phantomsection\\label{{{}}} {}",
+                self.label(chapter, key),
+                self.references[key].format()
+            )
+            .unwrap();
+        }
+        references.push_str("\\end{list}\n");
+
+        references
+    }
+}
+
+impl Reference {
+    /// Authors as cited: "Knuth", "Knuth and Lamport" or "Knuth et al.".
+    fn short_authors(&self) -> String {
+        match self.authors.as_slice() {
+            [] => self.title.clone().unwrap_or_default(),
+            [a] => a.0.clone(),
+            [a, b] => format!("{} and {}", a.0, b.0),
+            [a, ..] => format!("{} et al.", a.0),
+        }
+    }
+
+    /// The entry in the references section.
+    fn format(&self) -> String {
+        let mut entry = String::new();
+
+        let authors: Vec<String> = self
+            .authors
+            .iter()
+            .map(|(family, given)| {
+                let initials: Vec<String> = given
+                    .split(|c: char| c.is_whitespace() || c == '-')
+                    .filter_map(|g| g.trim_start_matches('{').chars().next())
+                    .map(|c| format!("{}.", c))
+                    .collect();
+                if initials.is_empty() {
+                    family.clone()
+                } else {
+                    format!("{}, {}", family, initials.join(" "))
+                }
+            })
+            .collect();
+        if !authors.is_empty() {
+            write!(entry, "{} ", authors.join(", ")).unwrap();
+        }
+        write!(entry, "({}). ", self.year.as_deref().unwrap_or("n.d.")).unwrap();
+
+        // Titles of standalone works are set in italics, otherwise their container is.
+        if let Some(title) = &self.title {
+            if self.container.is_none() {
+                write!(entry, "\\emph{{{}}}. ", title).unwrap();
+            } else {
+                write!(entry, "{}. ", title).unwrap();
+            }
+        }
+        if let Some(container) = &self.container {
+            write!(entry, "\\emph{{{}}}", container).unwrap();
+            if let Some(volume) = &self.volume {
+                write!(entry, ", {}", volume).unwrap();
+            }
+            if let Some(number) = &self.number {
+                write!(entry, "({})", number).unwrap();
+            }
+            if let Some(pages) = &self.pages {
+                write!(entry, ", {}", pages).unwrap();
+            }
+            entry.push_str(". ");
+        }
+        if let Some(publisher) = &self.publisher {
+            write!(entry, "{}. ", publisher).unwrap();
+        }
+
+        match (&self.doi, &self.url) {
+            (Some(doi), _) => {
+                let url = format!("https://doi.org/{}", doi);
+                write!(entry, "\\url{{{}}}", escape(&url, EscapeMode::Url)).unwrap()
+            }
+            (None, Some(url)) => {
+                write!(entry, "\\url{{{}}}", escape(url, EscapeMode::Url)).unwrap()
+            }
+            (None, None) => {}
+        }
+
+        entry.trim_end().to_string()
+    }
+}
+
+/// Reads a braced, quoted or bare BibTeX value (with `#` concatenation),
+/// returning it and the rest of the source.
+fn bibtex_value(source: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut rest = source.trim_start();
+
+    loop {
+        let end = if rest.starts_with('{') || rest.starts_with('"') {
+            let quoted = rest.starts_with('"');
+            let mut depth = 0;
+            let mut end = rest.len();
+            for (i, c) in rest.char_indices() {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    '"' if quoted && i > 0 && depth == 0 => {
+                        end = i;
+                        break;
+                    }
+                    _ => {}
+                }
+                if !quoted && depth == 0 {
+                    end = i;
+                    break;
+                }
+            }
+            value.push_str(&rest[1..end]);
+            (end + 1).min(rest.len())
+        } else {
+            let end = rest.find([',', '}', ')', '#']).unwrap_or(rest.len());
+            value.push_str(rest[..end].trim());
+            end
+        };
+
+        rest = rest[end..].trim_start();
+        match rest.strip_prefix('#') {
+            Some(more) => rest = more.trim_start(),
+            None => break,
+        }
+    }
+
+    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    (value, rest)
+}
+
+/// Skips a braced or parenthesized body and returns the source after it.
+fn skip_balanced(source: &str) -> &str {
+    let mut depth = 0;
+    for (i, c) in source.char_indices() {
+        match c {
+            '{' | '(' => depth += 1,
+            '}' | ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return &source[i + 1..];
+        }
+    }
+    ""
+}
+
+/// Splits a BibTeX name list ("Knuth, Donald E. and Leslie Lamport") into `(family, given)`.
+fn bibtex_names(names: &str) -> Vec<(String, String)> {
+    // " and " only separates names outside of braces.
+    let mut list = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in names.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ if depth == 0 && names[i..].starts_with(" and ") => {
+                list.push(&names[start..i]);
+                start = i + " and ".len();
+            }
+            _ => {}
+        }
+    }
+    list.push(&names[start..]);
+
+    list.into_iter()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            // "{World Health Organization}" is a single family name.
+            if name.starts_with('{') && name.ends_with('}') {
+                return (name[1..name.len() - 1].to_string(), String::new());
+            }
+            match name.split_once(',') {
+                Some((family, given)) => (family.trim().to_string(), given.trim().to_string()),
+                None => match name.rsplit_once(' ') {
+                    Some((given, family)) => (family.to_string(), given.to_string()),
+                    None => (name.to_string(), String::new()),
+                },
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIBTEX: &str = r#"
+@comment{ignore @fake{x, title = {No}} }
+@string{acm = "ACM"}
+@book{knuth84,
+  author = {Donald E. Knuth},
+  title = {The {\TeX}book},
+  year = 1984,
+}
+@article(lamport,
+  author = "Leslie Lamport and Others",
+  year = {1994},
+)
+"#;
+
+    #[test]
+    fn parses_bibtex_entries_only() {
+        let bibliography = Bibliography::from_bibtex(BIBTEX);
+        let keys: Vec<&str> = bibliography.references.keys().map(String::as_str).collect();
+        assert_eq!(keys, ["knuth84", "lamport"]);
+
+        let knuth = &bibliography.references["knuth84"];
+        assert_eq!(knuth.year.as_deref(), Some("1984"));
+        assert_eq!(knuth.authors[0].0, "Knuth");
+    }
+
+    #[test]
+    fn writes_citation_groups() {
+        let bibliography = Bibliography::from_bibtex(BIBTEX);
+        let mut wr = TexWriter::new(String::new());
+
+        let keys = bibliography.write_citation(&mut wr, None, "see @knuth84, p. 3; -@lamport");
+        assert_eq!(
+            keys,
+            Some(vec!["knuth84".to_string(), "lamport".to_string()])
+        );
+        assert_eq!(
+            wr.into_buffer(),
+            r"(see \hyperref[cite:knuth84]{Knuth 1984}, p. 3; \hyperref[cite:lamport]{1994})"
+        );
+    }
+
+    #[test]
+    fn ignores_brackets_that_arent_citations() {
+        let bibliography = Bibliography::from_bibtex(BIBTEX);
+        let mut wr = TexWriter::new(String::new());
+
+        assert_eq!(
+            bibliography.write_citation(&mut wr, None, "mail me@example.com"),
+            None
+        );
+        assert_eq!(bibliography.write_citation(&mut wr, None, "@unknown"), None);
+        assert!(wr.into_buffer().is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+use super::label;
+use super::writer::TexWriter;
+use regex::Regex;
+use std::fmt::Write;
+
+/// Label prefixes of numbered figures, tables and listings, as in `[see](#fig:arch)`.
+const PREFIXES: [&str; 3] = ["fig:", "tbl:", "lst:"];
+
+/// Caption of a figure, table or listing, with an optional `{#id}` label.
+#[derive(Debug)]
+pub(crate) struct Caption {
+    pub(crate) text: String,
+    pub(crate) label: Option<String>,
+}
+
+impl Caption {
+    pub(crate) fn new(caption: &str) -> Caption {
+        let regex_label = Regex::new(r"\s*\{#([^}\s]+)\}\s*$").unwrap();
+
+        match regex_label.captures(caption) {
+            Some(cap) => Caption {
+                text: caption[..cap.get(0).unwrap().start()].trim().to_string(),
+                label: Some(cap[1].to_string()),
+            },
+            None => Caption {
+                text: caption.trim().to_string(),
+                label: None,
+            },
+        }
+    }
+
+    /// Writes `\caption`, and the label prefixed with the chapter like heading labels.
+    pub(crate) fn write(&self, wr: &mut TexWriter<String>, chapter: Option<&str>) {
+        wr.push_str(r"\caption{").escape_str(&self.text).push('}');
+        if let Some(id) = &self.label {
+            write!(wr, r"\label{{{}}}", label(chapter, Some(id.as_str()))).unwrap();
+        }
+    }
+}
+
+/// Caption from an italic line (`*caption*` or `_caption_`) at the start of `rest`
+/// or on the next line, with the length of source it takes up. An italic paragraph
+/// after a blank line stays a paragraph.
+fn italic_caption(rest: &str) -> Option<(Caption, usize)> {
+    let regex_italic =
+        Regex::new(r"^[ \t]*(\r?\n[ \t]*)?(\*([^*\n]+)\*|_([^_\n]+)_)[ \t]*(\r?\n|$)").unwrap();
+
+    let cap = regex_italic.captures(rest)?;
+    let text = cap.get(3).or_else(|| cap.get(4)).unwrap().as_str();
+
+    Some((Caption::new(text), cap.get(0).unwrap().end()))
+}
+
+/// Caption of an image from an italic line after it, if the image is alone on its line,
+/// or else from its title.
+pub(crate) fn image_caption(before: &str, title: &str, rest: &str) -> Option<(Caption, usize)> {
+    let alone = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .is_empty()
+        && rest
+            .trim_start_matches([' ', '\t'])
+            .starts_with(['\r', '\n']);
+
+    if alone {
+        if let Some(caption) = italic_caption(rest) {
+            return Some(caption);
+        }
+    }
+
+    (!title.trim().is_empty()).then(|| (Caption::new(title), 0))
+}
+
+/// Caption of a code block from its info string (`title="..."` and `{#lst:id}`),
+/// or else from an italic line after it.
+pub(crate) fn code_caption(info: &str, rest: &str) -> Option<(Caption, usize)> {
+    let regex_title = Regex::new(r#"title="([^"]*)""#).unwrap();
+    let regex_label = Regex::new(r"\{#([^}\s]+)\}").unwrap();
+
+    let title = regex_title.captures(info).map(|cap| cap[1].to_string());
+    let label = regex_label.captures(info).map(|cap| cap[1].to_string());
+
+    if title.is_some() || label.is_some() {
+        return Some((
+            Caption {
+                text: title.unwrap_or_default(),
+                label,
+            },
+            0,
+        ));
+    }
+
+    italic_caption(rest)
+}
+
+/// Whether a link points at a numbered figure, table or listing.
+pub(crate) fn is_reference(url: &str) -> bool {
+    matches!(url.split_once('#'), Some((_, id)) if PREFIXES.iter().any(|p| id.starts_with(p)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captions_from_the_next_line_only() {
+        let (caption, len) = image_caption("", "", "\n*The plan {#fig:plan}*\nmore").unwrap();
+        assert_eq!(caption.text, "The plan");
+        assert_eq!(caption.label.as_deref(), Some("fig:plan"));
+        assert_eq!(len, "\n*The plan {#fig:plan}*\n".len());
+
+        assert!(code_caption("rust", "\n_Main loop_").is_some());
+        assert!(code_caption("rust", "\n\n*Separate paragraph*\n").is_none());
+        assert!(image_caption("", "", "\n\n*Separate paragraph*\n").is_none());
+    }
+}
@@ -1,3 +1,8 @@
+use super::{Bibliography, Diagrams, Extension, GlossaryEntry, IndexEntry, TableStyle};
+use pulldown_cmark::Event;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::path::Path;
 
 pub struct Converter<'a> {
@@ -6,7 +11,27 @@ pub struct Converter<'a> {
     pub(crate) assets: Option<&'a Path>,
     pub(crate) dest: Option<&'a Path>,
     pub(crate) chap_offset: i32,
-    pub(crate) code_utf8_escape: Option<(&'a str, &'a str)>,
+    pub(crate) link_base: Option<&'a str>,
+    pub(crate) section_number: Option<&'a [u32]>,
+    pub(crate) admonitions: Option<&'a BTreeMap<String, String>>,
+    pub(crate) extensions: Option<&'a [Extension]>,
+    pub(crate) table_style: TableStyle,
+    pub(crate) chapter_path: Option<&'a str>,
+    pub(crate) index: Option<&'a RefCell<Vec<IndexEntry>>>,
+    pub(crate) index_code: bool,
+    pub(crate) glossary: Option<&'a [GlossaryEntry]>,
+    pub(crate) bibliography: Option<&'a Bibliography>,
+    pub(crate) citations: Option<&'a RefCell<Vec<String>>>,
+    pub(crate) diagrams: Option<&'a Diagrams>,
+    pub(crate) emoji_images: Option<&'a Path>,
+    pub(crate) language: Option<&'a str>,
+    /// Whether the content is the body of an admonition, which can't hold sections.
+    pub(crate) in_admonition: bool,
+    pub(crate) unicode: Option<&'a BTreeMap<char, String>>,
+    pub(crate) enumitem: bool,
+    /// Events of part of the content to convert instead of parsing all of it,
+    /// like the Markdown between HTML tags.
+    pub(crate) events: Option<&'a [(Event<'a>, Range<usize>)]>,
 }
 
 impl<'a> Converter<'a> {
@@ -17,26 +42,43 @@ impl<'a> Converter<'a> {
             assets: None,
             dest: None,
             chap_offset: 0,
-            code_utf8_escape: None,
+            link_base: None,
+            section_number: None,
+            admonitions: None,
+            extensions: None,
+            table_style: TableStyle::default(),
+            chapter_path: None,
+            index: None,
+            index_code: false,
+            glossary: None,
+            bibliography: None,
+            citations: None,
+            diagrams: None,
+            emoji_images: None,
+            language: None,
+            in_admonition: false,
+            unicode: None,
+            enumitem: true,
+            events: None,
         }
     }
 
     #[allow(dead_code)]
-    pub fn template(self, template: &'a str) -> Converter {
+    pub fn template(self, template: &'a str) -> Converter<'a> {
         Converter {
             template: Some(template),
             ..self
         }
     }
 
-    pub fn assets(self, assets: &'a Path) -> Converter {
+    pub fn assets(self, assets: &'a Path) -> Converter<'a> {
         Converter {
             assets: Some(assets),
             ..self
         }
     }
 
-    pub fn dest(self, dest: &'a Path) -> Converter {
+    pub fn dest(self, dest: &'a Path) -> Converter<'a> {
         Converter {
             dest: Some(dest),
             ..self
@@ -48,10 +90,98 @@ impl<'a> Converter<'a> {
         self
     }
 
-    /// Set escape for UTF-8 characters inside code listings
-    #[allow(dead_code)]
-    pub fn code_utf8_escape(mut self, start_escape: &'a str, end_escape: &'a str) -> Self {
-        self.code_utf8_escape = Some((start_escape, end_escape));
+    /// Number chapter headings after mdBook's section number, e.g. `[3, 2]` for "3.2."
+    pub fn section_number(mut self, number: &'a [u32]) -> Self {
+        self.section_number = Some(number);
+        self
+    }
+
+    /// Render admonitions of the given kinds (mapped to their colours) as `admonition` boxes
+    pub fn admonitions(mut self, admonitions: &'a BTreeMap<String, String>) -> Self {
+        self.admonitions = Some(admonitions);
+        self
+    }
+
+    /// Markdown extensions to enable instead of the default set
+    pub fn extensions(mut self, extensions: &'a [Extension]) -> Self {
+        self.extensions = Some(extensions);
+        self
+    }
+
+    /// Rules and colours used for Markdown tables
+    pub fn table_style(mut self, style: TableStyle) -> Self {
+        self.table_style = style;
+        self
+    }
+
+    /// Path of the chapter in the book's source, prefixing its labels (e.g. `guide/intro.md`)
+    pub fn chapter_path(mut self, path: &'a str) -> Self {
+        self.chapter_path = Some(path);
+        self
+    }
+
+    /// Collect index marks into `entries`, otherwise they are dropped
+    pub fn index(mut self, entries: &'a RefCell<Vec<IndexEntry>>) -> Self {
+        self.index = Some(entries);
+        self
+    }
+
+    /// Also index inline code identifiers
+    pub fn index_code(mut self, index_code: bool) -> Self {
+        self.index_code = index_code;
+        self
+    }
+
+    /// Link the first use of each glossary term to its entry in the glossary
+    pub fn glossary(mut self, glossary: &'a [GlossaryEntry]) -> Self {
+        self.glossary = Some(glossary);
+        self
+    }
+
+    /// Resolve `[@key]` citations against `bibliography`, collecting the cited keys
+    pub fn bibliography(
+        mut self,
+        bibliography: &'a Bibliography,
+        citations: &'a RefCell<Vec<String>>,
+    ) -> Self {
+        self.bibliography = Some(bibliography);
+        self.citations = Some(citations);
+        self
+    }
+
+    /// Render code blocks in the diagram languages of `diagrams` as figures
+    pub fn diagrams(mut self, diagrams: &'a Diagrams) -> Self {
+        self.diagrams = Some(diagrams);
+        self
+    }
+
+    /// Draw emoji with images from the SVG set in `images` rather than the emoji font
+    pub fn emoji_images(mut self, images: &'a Path) -> Self {
+        self.emoji_images = Some(images);
+        self
+    }
+
+    /// Set text in scripts other than that of the main polyglossia `language` in their own language
+    pub fn language(mut self, language: &'a str) -> Self {
+        self.language = Some(language);
+        self
+    }
+
+    /// Replace characters in text with LaTeX of their own, as character = "LaTeX"
+    pub fn unicode(mut self, rules: &'a BTreeMap<char, String>) -> Self {
+        self.unicode = Some(rules);
+        self
+    }
+
+    /// Whether the template loads enumitem, which list options like `noitemsep` need
+    pub fn enumitem(mut self, enumitem: bool) -> Self {
+        self.enumitem = enumitem;
+        self
+    }
+
+    /// Turn local links into URLs relative to `base` instead of internal references
+    pub fn link_base(mut self, base: &'a str) -> Self {
+        self.link_base = Some(base);
         self
     }
 
@@ -64,7 +194,7 @@ impl<'a> Converter<'a> {
                 output.push_str(template);
                 // Insert new LaTeX data into template after "\begin{document}".
                 let mark = "\\begin{document}";
-                let pos = template.find(&mark).unwrap() + mark.len();
+                let pos = template.find(mark).unwrap() + mark.len();
                 output.insert_str(pos, &latex);
             }
             None => output.push_str(&latex),
@@ -87,4 +217,5 @@ fn post_dirty_hack(output: String) -> String {
             "\n\\begin{shadedquotation}",
         )
         .replace("~\\\\\n\\begin{minted}", "\n\\begin{minted}")
+        .replace("~\\\\\n\n\\begin{admonition}", "\n\n\\begin{admonition}")
 }
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Commands rendering diagram code blocks to SVG, by language.
+///
+/// The source is written to the command's standard input and the SVG read from its output,
+/// unless the command takes `{input}` and `{output}` file arguments instead.
+pub const DIAGRAM_COMMANDS: [(&str, &str); 4] = [
+    ("dot", "dot -Tsvg"),
+    ("graphviz", "dot -Tsvg"),
+    ("mermaid", "mmdc --quiet --input {input} --output {output}"),
+    ("plantuml", "plantuml -tsvg -pipe"),
+];
+
+/// Renders diagrams with external commands, keeping the SVGs in a cache directory.
+#[derive(Debug, Clone)]
+pub struct Diagrams {
+    commands: BTreeMap<String, String>,
+    cache: PathBuf,
+}
+
+impl Diagrams {
+    pub fn new(commands: BTreeMap<String, String>, cache: impl Into<PathBuf>) -> Diagrams {
+        Diagrams {
+            commands,
+            cache: cache.into(),
+        }
+    }
+
+    /// Whether code blocks in `lang` are rendered as diagrams.
+    pub(crate) fn renders(&self, lang: &str) -> bool {
+        self.commands
+            .get(lang)
+            .map(|command| !command.trim().is_empty())
+            .unwrap_or_default()
+    }
+
+    /// Path of the SVG rendered from `source`, reusing the cached one if the source
+    /// and command haven't changed.
+    pub(crate) fn render(&self, lang: &str, source: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let command = self
+            .commands
+            .get(lang)
+            .ok_or_else(|| format!("no command for {} diagrams", lang))?;
+
+        let name = format!("{}-{:016x}", lang, hash(&[command.as_str(), source]));
+        let svg = self.cache.join(format!("{}.svg", name));
+        if svg.exists() {
+            return Ok(svg);
+        }
+        fs::create_dir_all(&self.cache)?;
+
+        let input = self.cache.join(format!("{}.{}", name, lang));
+        let mut args = command.split_whitespace().map(|arg| {
+            arg.replace("{input}", &input.to_string_lossy())
+                .replace("{output}", &svg.to_string_lossy())
+        });
+        let program = args.next().ok_or("empty diagram command")?;
+
+        let file_input = command.contains("{input}");
+        let file_output = command.contains("{output}");
+        if file_input {
+            fs::write(&input, source)?;
+        }
+
+        let mut child = Command::new(&program)
+            .args(args)
+            .stdin(if file_input {
+                Stdio::null()
+            } else {
+                Stdio::piped()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("unable to run `{}`: {}", program, e))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(source.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+
+        if file_input {
+            let _ = fs::remove_file(&input);
+        }
+        if !output.status.success() {
+            let _ = fs::remove_file(&svg);
+            return Err(format!(
+                "`{}` failed: {}",
+                program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        if !file_output {
+            fs::write(&svg, &output.stdout)?;
+        }
+
+        Ok(svg)
+    }
+}
+
+/// FNV-1a, which unlike the standard hasher stays the same across Rust releases.
+fn hash(parts: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        for byte in part.bytes().chain([0]) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
@@ -0,0 +1,122 @@
+use super::escape::{escape as escape_str, EscapeMode};
+use super::svg2png;
+use super::writer::TexWriter;
+use regex::Regex;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// An emoji with its modifier, or a keycap, flag, tag or ZWJ sequence.
+const EMOJI: &str = concat!(
+    r"\p{Regional_Indicator}{2}",
+    r"|[0-9#*]\x{FE0F}?\x{20E3}",
+    r"|\p{Extended_Pictographic}\x{FE0F}?\p{Emoji_Modifier}?",
+    r"(?:\x{200D}\p{Extended_Pictographic}\x{FE0F}?\p{Emoji_Modifier}?)+",
+    r"|(?:\p{Emoji_Presentation}|\p{Extended_Pictographic}\x{FE0F})\p{Emoji_Modifier}?",
+    r"[\x{E0020}-\x{E007E}]*\x{E007F}?",
+);
+
+/// Writes `text` with emoji as `\emojiimage` when `images` has an SVG for them, and as
+/// `\emoji` (set in the emoji font) otherwise, passing the text around them to `escape`.
+pub(crate) fn write_emoji(
+    wr: &mut TexWriter<String>,
+    text: &str,
+    images: Option<&Path>,
+    dest: Option<&Path>,
+    mut escape: impl FnMut(&mut TexWriter<String>, &str),
+) {
+    static REGEX_EMOJI: OnceLock<Regex> = OnceLock::new();
+    let regex_emoji = REGEX_EMOJI.get_or_init(|| Regex::new(EMOJI).unwrap());
+
+    let mut last = 0;
+    for m in regex_emoji.find_iter(text) {
+        escape(wr, &text[last..m.start()]);
+        last = m.end();
+
+        // Keycaps start with `#` or `*`.
+        let emoji = escape_str(m.as_str(), EscapeMode::Text);
+        match images.and_then(|images| emoji_image(m.as_str(), images, dest)) {
+            Some(png) => write!(wr, r"\emojiimage{{{}}}{{{}}}", png.to_string_lossy(), emoji),
+            None => write!(wr, r"\emoji{{{}}}", emoji),
+        }
+        .unwrap();
+    }
+    escape(wr, &text[last..]);
+}
+
+/// PNG of `emoji` rasterised from an SVG set named like Twemoji (`1f44d-1f3fb.svg`),
+/// OpenMoji (`1F44D-1F3FB.svg`) or Noto (`emoji_u1f44d_1f3fb.svg`).
+fn emoji_image(emoji: &str, images: &Path, dest: Option<&Path>) -> Option<PathBuf> {
+    let full: Vec<u32> = emoji.chars().map(|c| c as u32).collect();
+    // Sets usually drop the variation selector from file names.
+    let bare: Vec<u32> = full.iter().copied().filter(|&c| c != 0xFE0F).collect();
+
+    let name = |code_points: &[u32], separator: &str, format: fn(u32) -> String| {
+        code_points
+            .iter()
+            .map(|&c| format(c))
+            .collect::<Vec<_>>()
+            .join(separator)
+    };
+
+    let svg = [&bare, &full]
+        .iter()
+        .flat_map(|code_points| {
+            [
+                name(code_points, "-", |c| format!("{:x}", c)),
+                name(code_points, "-", |c| format!("{:04X}", c)),
+                format!(
+                    "emoji_u{}",
+                    name(code_points, "_", |c| format!("{:04x}", c))
+                ),
+            ]
+        })
+        .map(|name| images.join(format!("{}.svg", name)))
+        .find(|path| path.is_file())?;
+
+    let png = dest
+        .map(|p| p.to_path_buf())
+        .unwrap_or_default()
+        .join("emoji")
+        .join(format!("{}.png", name(&bare, "-", |c| format!("{:x}", c))));
+
+    // Each emoji is rasterised once per build.
+    if !png.exists() {
+        fs::create_dir_all(png.parent()?).ok()?;
+        svg2png(&svg).save_png(&png).ok()?;
+    }
+
+    Some(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emoji(text: &str) -> String {
+        let mut wr = TexWriter::new(String::new());
+        write_emoji(&mut wr, text, None, None, |wr, text| {
+            wr.escape_str(text);
+        });
+        wr.into_buffer()
+    }
+
+    #[test]
+    fn keeps_text_and_digits() {
+        assert_eq!(emoji("Chapter 1 # © ok"), r"Chapter 1 \# © ok");
+    }
+
+    #[test]
+    fn wraps_whole_sequences() {
+        assert_eq!(emoji("👍🏽 done"), r"\emoji{👍🏽} done");
+        assert_eq!(emoji("🇫🇷"), r"\emoji{🇫🇷}");
+        assert_eq!(emoji("👩‍💻!"), r"\emoji{👩‍💻}!");
+        assert_eq!(emoji("❤️"), r"\emoji{❤️}");
+    }
+
+    #[test]
+    fn escapes_keycaps() {
+        assert_eq!(emoji("#️⃣ 1️⃣"), r"\emoji{\#️⃣} \emoji{1️⃣}");
+    }
+}
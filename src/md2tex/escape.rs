@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+
+/// Where escaped text ends up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeMode {
+    /// Running text.
+    Text,
+    /// The URL argument of `\href`.
+    Url,
+    /// A `\label` or `\ref` key.
+    Label,
+    /// Inline code in `\texttt`, with break points after punctuation.
+    Code,
+}
+
+/// The ten LaTeX special characters, plus typographic replacements.
+const TEXT: [(char, &str); 11] = [
+    ('\\', r"\textbackslash{}"),
+    ('{', r"\{"),
+    ('}', r"\}"),
+    ('$', r"\$"),
+    ('&', r"\&"),
+    ('#', r"\#"),
+    ('^', r"\textasciicircum{}"),
+    ('_', r"\_"),
+    ('~', r"\textasciitilde{}"),
+    ('%', r"\%"),
+    ('—', "---"),
+];
+
+/// `hyperref` reads URLs almost verbatim, only these break the argument.
+const URL: [(char, &str); 5] = [
+    ('\\', r"\%5C"),
+    ('{', r"\%7B"),
+    ('}', r"\%7D"),
+    ('#', r"\#"),
+    ('%', r"\%"),
+];
+
+/// Characters that can't be part of a label key.
+const LABEL: [(char, &str); 10] = [
+    ('\\', "-"),
+    ('{', "-"),
+    ('}', "-"),
+    ('$', "-"),
+    ('&', "-"),
+    ('#', "-"),
+    ('^', "-"),
+    ('~', "-"),
+    ('%', "-"),
+    (',', "-"),
+];
+
+/// The specials again, straight quotes, and places where long identifiers may break.
+const CODE: [(char, &str); 20] = [
+    ('\\', r"\textbackslash{}"),
+    ('{', r"\{"),
+    ('}', r"\}"),
+    ('$', r"\$"),
+    ('&', r"\&"),
+    ('#', r"\#"),
+    ('^', r"\textasciicircum{}"),
+    ('_', r"\_\allowbreak{}"),
+    ('~', r"\textasciitilde{}"),
+    ('%', r"\%"),
+    ('\'', r"\textquotesingle{}"),
+    ('`', r"\textasciigrave{}"),
+    ('.', r".\allowbreak{}"),
+    (',', r",\allowbreak{}"),
+    (':', r":\allowbreak{}"),
+    ('/', r"/\allowbreak{}"),
+    ('(', r"(\allowbreak{}"),
+    ('=', r"=\allowbreak{}"),
+    ('\n', " "),
+    ('\t', "    "),
+];
+
+impl EscapeMode {
+    fn table(self) -> &'static [(char, &'static str)] {
+        match self {
+            EscapeMode::Text => &TEXT,
+            EscapeMode::Url => &URL,
+            EscapeMode::Label => &LABEL,
+            EscapeMode::Code => &CODE,
+        }
+    }
+}
+
+/// Escapes `s` for the given mode in a single pass.
+pub fn escape(s: &str, mode: EscapeMode) -> String {
+    escape_with(s, mode, &BTreeMap::new())
+}
+
+/// Escapes `s` like [`escape`], replacing the characters of `rules` with their LaTeX first.
+pub fn escape_with(s: &str, mode: EscapeMode, rules: &BTreeMap<char, String>) -> String {
+    let table = mode.table();
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if let Some(latex) = rules.get(&c) {
+            escaped.push_str(latex);
+            continue;
+        }
+        match table.iter().find(|(special, _)| *special == c) {
+            Some((_, replacement)) => escaped.push_str(replacement),
+            None => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_all_specials_in_text() {
+        assert_eq!(
+            escape(
+                r"C:\Users\{name} costs $5 & 100% #1 x^2 ~ y_z",
+                EscapeMode::Text
+            ),
+            r"C:\textbackslash{}Users\textbackslash{}\{name\} costs \$5 \& 100\% \#1 x\textasciicircum{}2 \textasciitilde{} y\_z"
+        );
+    }
+
+    #[test]
+    fn escapes_urls_and_labels() {
+        assert_eq!(
+            escape("https://example.com/a%20b#top", EscapeMode::Url),
+            r"https://example.com/a\%20b\#top"
+        );
+        assert_eq!(escape("a{b}#c,d", EscapeMode::Label), "a-b--c-d");
+    }
+
+    #[test]
+    fn unicode_rules_come_before_specials() {
+        let rules = BTreeMap::from([
+            ('✓', r"\checkmark{}".to_string()),
+            ('#', "sharp".to_string()),
+        ]);
+        assert_eq!(
+            escape_with("✓ #1 & more", EscapeMode::Text, &rules),
+            r"\checkmark{} sharp1 \& more"
+        );
+    }
+}
@@ -1,5 +1,5 @@
 /// Used to keep track of current pulldown_cmark "event".
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum EventType {
     Code,
     Emphasis,
@@ -7,12 +7,31 @@ pub enum EventType {
     Strong,
     Table,
     TableHead,
+    #[default]
     Text,
     BlockQuote,
+    Admonition,
+}
+
+/// How text is written, decided by the enclosing elements rather than the innermost one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextContext {
+    /// Code blocks, copied as is.
+    Verbatim,
+    /// Body of an admonition, kept as Markdown and converted afterwards.
+    Admonition,
+    /// Everything else, escaped (math is detected within the text).
+    Text,
 }
 
-impl Default for EventType {
-    fn default() -> Self {
-        Self::Text
+impl TextContext {
+    pub fn of(event_stack: &[EventType]) -> Self {
+        if event_stack.contains(&EventType::Admonition) {
+            Self::Admonition
+        } else if event_stack.contains(&EventType::Code) {
+            Self::Verbatim
+        } else {
+            Self::Text
+        }
     }
 }
@@ -0,0 +1,52 @@
+use pulldown_cmark::Options;
+use serde::{Deserialize, Serialize};
+
+/// Markdown extensions that can be enabled per book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Extension {
+    Tables,
+    Footnotes,
+    Strikethrough,
+    Tasklists,
+    SmartPunctuation,
+    /// `# Heading {#id .class}`, the id becomes a label and `.unnumbered` stars the heading.
+    HeadingAttributes,
+    /// `Term` followed by `: definition` lines, rendered as `description`.
+    DefinitionLists,
+    /// `^text^`, rendered with `\textsuperscript`.
+    Superscript,
+    /// `~text~` (single tildes, no spaces), rendered with `\textsubscript`.
+    Subscript,
+}
+
+/// Extensions enabled when none are configured.
+pub const DEFAULT_EXTENSIONS: [Extension; 5] = [
+    Extension::SmartPunctuation,
+    Extension::Strikethrough,
+    Extension::Footnotes,
+    Extension::Tasklists,
+    Extension::Tables,
+];
+
+/// Parser options for the given extensions.
+///
+/// Extensions handled by the converter itself don't map to a parser option.
+pub fn parser_options(extensions: &[Extension]) -> Options {
+    let mut options = Options::empty();
+
+    for extension in extensions {
+        options |= match extension {
+            Extension::Tables => Options::ENABLE_TABLES,
+            Extension::Footnotes => Options::ENABLE_FOOTNOTES,
+            Extension::Tasklists => Options::ENABLE_TASKLISTS,
+            Extension::SmartPunctuation => Options::ENABLE_SMART_PUNCTUATION,
+            Extension::HeadingAttributes => Options::ENABLE_HEADING_ATTRIBUTES,
+            // Single tildes are parsed as strikethrough and told apart by their source.
+            Extension::Strikethrough | Extension::Subscript => Options::ENABLE_STRIKETHROUGH,
+            Extension::DefinitionLists | Extension::Superscript => Options::empty(),
+        };
+    }
+
+    options
+}
@@ -0,0 +1,174 @@
+use super::escape::{escape, EscapeMode};
+use super::writer::TexWriter;
+use super::Converter;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// A glossary term, or an acronym when it has a long form.
+#[derive(Debug, Clone)]
+pub struct GlossaryEntry {
+    pub term: String,
+    pub long: Option<String>,
+    /// Markdown description.
+    pub description: String,
+}
+
+/// Parses a Markdown definition list of `Term` lines followed by `: description` lines.
+///
+/// Headings and anything before the first term are ignored.
+pub fn parse_glossary(markdown: &str) -> Vec<GlossaryEntry> {
+    let mut entries: Vec<GlossaryEntry> = Vec::new();
+    let mut term: Option<&str> = None;
+
+    for line in markdown.lines() {
+        let line = line.trim_end();
+
+        if let Some(description) = line.strip_prefix(": ") {
+            if let Some(term) = term.take() {
+                entries.push(GlossaryEntry {
+                    term: term.trim().to_string(),
+                    long: None,
+                    description: description.trim().to_string(),
+                });
+            } else if let Some(last) = entries.last_mut() {
+                write!(last.description, "\n\n{}", description.trim()).unwrap();
+            }
+        } else if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else if line.starts_with(char::is_whitespace) && term.is_none() {
+            // Indented continuation of the last description.
+            if let Some(last) = entries.last_mut() {
+                write!(last.description, " {}", line.trim()).unwrap();
+            }
+        } else {
+            term = Some(line);
+        }
+    }
+
+    entries
+}
+
+/// Label of a glossary term.
+fn glossary_label(term: &str) -> String {
+    format!("glossary:{}", escape(term, EscapeMode::Label))
+}
+
+/// Finds glossary terms in text, linking each one on its first use only.
+pub(crate) struct GlossaryLinker<'a> {
+    entries: &'a [GlossaryEntry],
+    regex: Regex,
+    used: HashSet<usize>,
+}
+
+impl<'a> GlossaryLinker<'a> {
+    pub(crate) fn new(entries: &'a [GlossaryEntry]) -> Option<Self> {
+        if entries.is_empty() {
+            return None;
+        }
+
+        // Longer terms first, so they win over terms they contain.
+        let mut terms: Vec<&str> = entries.iter().map(|e| e.term.as_str()).collect();
+        terms.sort_by_key(|t| std::cmp::Reverse(t.len()));
+
+        // Word boundaries only make sense next to Latin letters and digits.
+        let alternatives: Vec<String> = terms
+            .iter()
+            .map(|term| {
+                let start = term.starts_with(|c: char| c.is_ascii_alphanumeric());
+                let end = term.ends_with(|c: char| c.is_ascii_alphanumeric());
+                format!(
+                    "{}{}{}",
+                    if start { r"\b" } else { "" },
+                    regex::escape(term),
+                    if end { r"\b" } else { "" }
+                )
+            })
+            .collect();
+
+        Some(GlossaryLinker {
+            entries,
+            regex: Regex::new(&alternatives.join("|")).unwrap(),
+            used: HashSet::new(),
+        })
+    }
+
+    /// Ranges of terms used for the first time in `text`, with their entry.
+    pub(crate) fn first_uses(&mut self, text: &str) -> Vec<(Range<usize>, usize)> {
+        let mut uses = Vec::new();
+
+        for m in self.regex.find_iter(text) {
+            if let Some(i) = self.entries.iter().position(|e| e.term == m.as_str()) {
+                if self.used.insert(i) {
+                    uses.push((m.range(), i));
+                }
+            }
+        }
+
+        uses
+    }
+
+    /// Writes the link to an entry, spelling out acronyms.
+    pub(crate) fn write_link(&self, wr: &mut TexWriter<String>, entry: usize) {
+        let entry = &self.entries[entry];
+
+        write!(wr, r"\hyperref[{}]{{", glossary_label(&entry.term)).unwrap();
+        match &entry.long {
+            Some(long) => {
+                wr.escape_str(long)
+                    .push_str(" (")
+                    .escape_str(&entry.term)
+                    .push(')');
+            }
+            None => {
+                wr.escape_str(&entry.term);
+            }
+        }
+        wr.push('}');
+    }
+}
+
+/// The glossary appendix, with every term in alphabetical order.
+pub fn print_glossary(entries: &[GlossaryEntry], title: &str) -> String {
+    let mut entries: Vec<&GlossaryEntry> = entries.iter().collect();
+    entries.sort_by_cached_key(|e| e.term.to_lowercase());
+
+    let mut glossary = String::new();
+    writeln!(
+        glossary,
+        "\\chapter*{{{}}}",
+        escape(title, EscapeMode::Text)
+    )
+    .unwrap();
+    writeln!(
+        glossary,
+        "\\addcontentsline{{toc}}{{chapter}}{{{}}}",
+        escape(title, EscapeMode::Text)
+    )
+    .unwrap();
+    glossary.push_str("\\begin{description}\n");
+
+    for entry in entries {
+        write!(
+            glossary,
+            "\\item[{{{}}}]\\phantomsection\\label{{{}}} ",
+            escape(&entry.term, EscapeMode::Text),
+            glossary_label(&entry.term)
+        )
+        .unwrap();
+
+        if let Some(long) = &entry.long {
+            write!(glossary, "\\emph{{{}}}. ", escape(long, EscapeMode::Text)).unwrap();
+        }
+
+        // The description is Markdown, without the line break after its last paragraph.
+        let description = Converter::new(&entry.description).run();
+        let description = description.trim();
+        let description = description.strip_suffix(r"~\\").unwrap_or(description);
+        writeln!(glossary, "{}", description.trim()).unwrap();
+    }
+
+    glossary.push_str("\\end{description}\n");
+    glossary
+}
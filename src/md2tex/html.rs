@@ -0,0 +1,836 @@
+use super::caption::Caption;
+use super::emoji;
+use super::escape::{escape, escape_with, EscapeMode};
+use super::script::ScriptRuns;
+use super::writer::TexWriter;
+use super::{local_label, online_url, write_code, write_image, Converter, TableStyle};
+use crate::rcdom::{Handle, NodeData, RcDom};
+use html5ever::driver::ParseOpts;
+use html5ever::tendril::TendrilSink;
+use html5ever::{local_name, namespace_url, ns, parse_fragment, QualName};
+use pulldown_cmark::{Event, Tag};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// Elements without a closing tag.
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements that may wrap whole Markdown blocks, like `<details>` around paragraphs.
+const WRAPPER_ELEMENTS: [&str; 20] = [
+    "article",
+    "aside",
+    "blockquote",
+    "center",
+    "details",
+    "div",
+    "dl",
+    "figure",
+    "footer",
+    "header",
+    "main",
+    "nav",
+    "ol",
+    "section",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+/// Placeholder for the LaTeX of Markdown between HTML tags.
+const LATEX_ELEMENT: &str = "mdbook-latex";
+
+/// Collects the events between an HTML opening tag and its closing tag,
+/// so that they are parsed as a single fragment.
+///
+/// Markdown events inside the fragment are converted to LaTeX on their own.
+pub struct HtmlAccumulator<'a> {
+    events: Vec<(Event<'a>, Range<usize>)>,
+    open_tags: Vec<String>,
+    /// Whether the HTML started within a paragraph, which it can't outlast.
+    inline: bool,
+    regex_tag: Regex,
+    regex_comment: Regex,
+}
+
+impl<'a> HtmlAccumulator<'a> {
+    pub fn new() -> Self {
+        Self {
+            events: Vec::new(),
+            open_tags: Vec::new(),
+            inline: false,
+            regex_tag: Regex::new(r"<(/?)([A-Za-z][A-Za-z0-9-]*)[^>]*?(/?)>").unwrap(),
+            regex_comment: Regex::new(r"(?s)<!--.*?-->").unwrap(),
+        }
+    }
+
+    /// Whether events are being collected.
+    pub fn is_open(&self) -> bool {
+        !self.events.is_empty()
+    }
+
+    /// Adds an event, returns `true` once all opened tags are closed.
+    pub fn push(&mut self, event: Event<'a>, range: Range<usize>) -> bool {
+        if let Event::Html(ref html) = event {
+            // Block HTML comes in whole lines, inline HTML as single tags.
+            if self.events.is_empty() {
+                self.inline = !html.ends_with('\n');
+            }
+
+            let mut open_tags = std::mem::take(&mut self.open_tags);
+            self.track_tags(html, &mut open_tags);
+            self.open_tags = open_tags;
+        }
+
+        self.events.push((event, range));
+        self.open_tags.is_empty()
+    }
+
+    /// Updates the tags left open by a piece of HTML.
+    fn track_tags(&self, html: &str, open_tags: &mut Vec<String>) {
+        let html = self.regex_comment.replace_all(html, "");
+
+        for caps in self.regex_tag.captures_iter(&html) {
+            let name = caps[2].to_lowercase();
+
+            if &caps[1] == "/" {
+                // Closing a tag also closes anything left open inside it.
+                if let Some(pos) = open_tags.iter().rposition(|t| *t == name) {
+                    open_tags.truncate(pos);
+                }
+            } else if &caps[3] != "/" && !VOID_ELEMENTS.contains(&name.as_str()) {
+                open_tags.push(name);
+            }
+        }
+    }
+
+    /// Whether `event` ends the block the collected HTML belongs to while its tags are
+    /// still open: the end of the paragraph for inline HTML, any heading, or the start
+    /// of another block unless the outermost tag wraps Markdown blocks.
+    pub fn is_interrupted_by(&self, event: &Event) -> bool {
+        if self.inline {
+            return matches!(event, Event::End(Tag::Paragraph));
+        }
+
+        let wrapper = self
+            .open_tags
+            .first()
+            .filter(|tag| WRAPPER_ELEMENTS.contains(&tag.as_str()))
+            .is_some();
+
+        match event {
+            Event::Start(Tag::Heading(..)) => true,
+            Event::Start(
+                Tag::Paragraph | Tag::List(_) | Tag::CodeBlock(_) | Tag::BlockQuote | Tag::Table(_),
+            )
+            | Event::Rule => !wrapper,
+            _ => false,
+        }
+    }
+
+    /// Parses the collected events and starts over.
+    ///
+    /// Markdown between the tags is converted like the rest of the chapter, and its LaTeX
+    /// kept in a placeholder element. Table cells can't hold the converter's paragraphs,
+    /// so the Markdown in them goes through HTML instead.
+    pub fn finish(&mut self, converter: &Converter) -> RcDom {
+        let mut html = String::new();
+        let mut open_tags = Vec::new();
+        let mut markdown = Vec::new();
+        let mut depth = 0;
+
+        for (event, range) in std::mem::take(&mut self.events) {
+            match event {
+                Event::Html(ref tag) if depth == 0 => {
+                    self.push_markdown(&mut html, converter, &open_tags, &mut markdown);
+                    self.track_tags(tag, &mut open_tags);
+                    html.push_str(tag);
+                    continue;
+                }
+                Event::Start(_) => depth += 1,
+                // Blocks that started before the HTML end outside of it.
+                Event::End(_) if depth == 0 => continue,
+                Event::End(_) => depth -= 1,
+                _ => {}
+            }
+            markdown.push((event, range));
+        }
+        self.push_markdown(&mut html, converter, &open_tags, &mut markdown);
+        self.open_tags.clear();
+
+        parse_fragment_str(&html)
+    }
+
+    /// Adds the Markdown events collected since the last HTML to `html`.
+    fn push_markdown(
+        &self,
+        html: &mut String,
+        converter: &Converter,
+        open_tags: &[String],
+        markdown: &mut Vec<(Event<'a>, Range<usize>)>,
+    ) {
+        if markdown.is_empty() {
+            return;
+        }
+
+        if open_tags.iter().any(|tag| tag == "td" || tag == "th") {
+            pulldown_cmark::html::push_html(html, markdown.drain(..).map(|(e, _)| e));
+            return;
+        }
+
+        let inner = Converter {
+            template: None,
+            section_number: None,
+            events: Some(markdown),
+            ..*converter
+        };
+        let latex = super::convert(&inner);
+        // Inline Markdown continues the line it's on.
+        let latex = match self.inline {
+            true => latex.trim_start_matches('\n'),
+            false => &latex,
+        };
+
+        write!(
+            html,
+            r#"<{} latex="{}"></{0}>"#,
+            LATEX_ELEMENT,
+            escape_attribute(latex)
+        )
+        .unwrap();
+        markdown.clear();
+    }
+
+    /// Gives up on tags that aren't closed: parses only the HTML they were opened in and
+    /// returns the events after it, to be converted as Markdown again.
+    pub fn abandon(&mut self, converter: &Converter) -> (RcDom, Vec<(Event<'a>, Range<usize>)>) {
+        let html_events = self
+            .events
+            .iter()
+            .take_while(|(event, _)| matches!(event, Event::Html(_)))
+            .count();
+
+        let rest = self.events.split_off(html_events);
+        (self.finish(converter), rest)
+    }
+}
+
+/// Escapes text for an HTML attribute value in double quotes.
+fn escape_attribute(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Parses an HTML fragment as the content of a `<body>`.
+fn parse_fragment_str(html: &str) -> RcDom {
+    parse_fragment(
+        RcDom::default(),
+        ParseOpts::default(),
+        QualName::new(None, ns!(html), local_name!("body")),
+        Vec::new(),
+    )
+    .from_utf8()
+    .read_from(&mut html.as_bytes())
+    .unwrap()
+}
+
+impl<'a> Default for HtmlAccumulator<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes the LaTeX for the children of an HTML node.
+pub fn write_children(
+    wr: &mut TexWriter<String>,
+    converter: &Converter,
+    node: &Handle,
+    in_table: bool,
+) {
+    for child in node.children.borrow().iter() {
+        write_node(wr, converter, child, in_table);
+    }
+}
+
+/// Writes the LaTeX for an HTML node.
+///
+/// Unsupported elements are reported on stderr and only their content is kept.
+/// Images inside table cells (`in_table`) are placed inline instead of as figures.
+pub fn write_node(
+    wr: &mut TexWriter<String>,
+    converter: &Converter,
+    node: &Handle,
+    in_table: bool,
+) {
+    let name = match node.data {
+        NodeData::Element { ref name, .. } => name.local.as_ref(),
+        NodeData::Text { ref contents } => {
+            let (images, dest) = (converter.emoji_images, converter.dest);
+            let no_rules = BTreeMap::new();
+            let rules = converter.unicode.unwrap_or(&no_rules);
+            let scripts = converter.language.map(ScriptRuns::new);
+            emoji::write_emoji(
+                wr,
+                &contents.borrow(),
+                images,
+                dest,
+                |wr, text| match &scripts {
+                    Some(scripts) => scripts.write(wr, text, rules),
+                    None => {
+                        wr.push_str(&escape_with(text, EscapeMode::Text, rules));
+                    }
+                },
+            );
+            return;
+        }
+        NodeData::Document => {
+            write_children(wr, converter, node, in_table);
+            return;
+        }
+        _ => return,
+    };
+
+    let attr = |key: &str| attribute(node, key);
+
+    match name {
+        LATEX_ELEMENT => {
+            wr.push_str(&attr("latex").unwrap_or_default());
+        }
+        "br" => {
+            wr.push_str(r"\newline{}").new_line();
+        }
+        "hr" => {
+            wr.new_line()
+                .push_str(r"\noindent\rule{\textwidth}{0.4pt}")
+                .new_line();
+        }
+        "img" => {
+            if let Some(src) = attr("src") {
+                // Like a Markdown image, the title captions the figure.
+                let caption = attr("title")
+                    .filter(|title| !in_table && !title.trim().is_empty())
+                    .map(|title| Caption::new(&title));
+                write_image(wr, converter, &src, in_table, caption.as_ref());
+            }
+        }
+
+        "b" | "strong" => wrap(wr, converter, node, in_table, r"\textbf{", "}"),
+        "i" | "em" | "cite" | "var" | "dfn" => wrap(wr, converter, node, in_table, r"\emph{", "}"),
+        "u" | "ins" => wrap(wr, converter, node, in_table, r"\uline{", "}"),
+        "s" | "del" | "strike" => wrap(wr, converter, node, in_table, r"\sout{", "}"),
+        "sup" => wrap(wr, converter, node, in_table, r"\textsuperscript{", "}"),
+        "sub" => wrap(wr, converter, node, in_table, r"\textsubscript{", "}"),
+        "small" => wrap(wr, converter, node, in_table, r"{\small ", "}"),
+        "code" | "tt" | "samp" => {
+            let mut code = String::new();
+            collect_text(node, &mut code);
+            write_code(wr, &code);
+        }
+        "kbd" => wrap(wr, converter, node, in_table, r"\fbox{\texttt{", "}}"),
+        "mark" => wrap(wr, converter, node, in_table, r"\colorbox{yellow}{", "}"),
+        "q" => wrap(wr, converter, node, in_table, "``", "''"),
+
+        "a" => match attr("href") {
+            Some(href) => {
+                let online = online_url(converter.link_base, &href);
+                match (online, local_label(converter.chapter_path, &href)) {
+                    (Some(target), _) => {
+                        write!(wr, r"\href{{{}}}{{", escape(&target, EscapeMode::Url))
+                    }
+                    (None, Some(label)) => write!(wr, r"\hyperref[{}]{{", label),
+                    (None, None) => write!(wr, r"\href{{{}}}{{", escape(&href, EscapeMode::Url)),
+                }
+                .unwrap();
+                write_children(wr, converter, node, in_table);
+                wr.push('}');
+            }
+            None => write_children(wr, converter, node, in_table),
+        },
+        "span" | "font" | "abbr" | "time" | "label" | "bdi" | "bdo" => {
+            write_children(wr, converter, node, in_table)
+        }
+
+        "p" | "div" | "section" | "article" | "aside" | "header" | "footer" | "main" | "nav"
+        | "figure" => {
+            wr.new_line();
+            write_children(wr, converter, node, in_table);
+            wr.new_line().new_line();
+        }
+        "center" => wrap(
+            wr,
+            converter,
+            node,
+            in_table,
+            "\n\\begin{center}\n",
+            "\n\\end{center}\n",
+        ),
+        "figcaption" => wrap(
+            wr,
+            converter,
+            node,
+            in_table,
+            "\n\\begin{center}\\small ",
+            "\\end{center}\n",
+        ),
+        "blockquote" => wrap(
+            wr,
+            converter,
+            node,
+            in_table,
+            "\n\\begin{shadedquotation}\n",
+            "\n\\end{shadedquotation}\n",
+        ),
+
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            wr.new_line().push_str(r"\paragraph*{");
+            write_children(wr, converter, node, in_table);
+            wr.push('}').new_line().push_str(r"\mbox{}\\").new_line();
+        }
+
+        "ul" => wrap(
+            wr,
+            converter,
+            node,
+            in_table,
+            "\n\\begin{itemize}\n",
+            "\\end{itemize}\n",
+        ),
+        "ol" => {
+            wr.new_line().push_str(r"\begin{enumerate}");
+            if let Some(start) = attr("start").and_then(|s| s.parse::<i64>().ok()) {
+                write!(wr, "[start={}]", start).unwrap();
+            }
+            wr.new_line();
+            write_children(wr, converter, node, in_table);
+            wr.push_str(r"\end{enumerate}").new_line();
+        }
+        "li" => wrap(wr, converter, node, in_table, r"\item ", "\n"),
+        "dl" => wrap(
+            wr,
+            converter,
+            node,
+            in_table,
+            "\n\\begin{description}\n",
+            "\\end{description}\n",
+        ),
+        "dt" => wrap(wr, converter, node, in_table, r"\item[{", "}] "),
+        "dd" => wrap(wr, converter, node, in_table, "", "\n"),
+
+        "pre" => {
+            let mut text = String::new();
+            collect_text(node, &mut text);
+
+            // `<pre><code class="language-rust">` as rendered by Markdown processors.
+            let code = node.children.borrow().iter().find(|child| {
+                matches!(child.data, NodeData::Element { ref name, .. } if name.local.as_ref() == "code")
+            }).cloned();
+            let lang = std::iter::once(node)
+                .chain(code.as_ref())
+                .filter_map(|node| attribute(node, "class"))
+                .find_map(|class| {
+                    class
+                        .split_whitespace()
+                        .find_map(|class| class.strip_prefix("language-").map(str::to_string))
+                })
+                .unwrap_or_else(|| "text".to_string());
+
+            wr.new_line();
+            writeln!(wr, r"\begin{{minted}}{{{}}}", lang).unwrap();
+            wr.push_str(text.trim_end_matches('\n'))
+                .new_line()
+                .push_str(r"\end{minted}")
+                .new_line();
+        }
+
+        "table" => write_table(wr, converter, node, in_table, None),
+        // Only reached for table parts outside of a table.
+        "thead" | "tbody" | "tfoot" | "tr" | "td" | "th" | "caption" => {
+            write_children(wr, converter, node, in_table)
+        }
+
+        // Collapsed content is always shown in print, headed by its summary.
+        "details" => {
+            wr.new_line();
+            write_children(wr, converter, node, in_table);
+            wr.new_line();
+        }
+        "summary" => {
+            wr.push_str(r"\textbf{");
+            write_children(wr, converter, node, in_table);
+            wr.push('}').new_line().new_line();
+        }
+
+        "script" | "style" | "head" | "title" | "meta" | "link" | "noscript" | "template" => {}
+
+        "html" | "body" => write_children(wr, converter, node, in_table),
+
+        _ => {
+            eprintln!(
+                "Warning: HTML element <{}> isn't supported in LaTeX, only its text is kept",
+                name
+            );
+            write_children(wr, converter, node, in_table);
+        }
+    }
+}
+
+/// A `<td>` or `<th>` converted to LaTeX.
+struct TableCell {
+    latex: String,
+    colspan: usize,
+    rowspan: usize,
+    header: bool,
+}
+
+/// A `<tr>`, flagged if it belongs to the table head.
+struct TableRow {
+    cells: Vec<TableCell>,
+    head: bool,
+}
+
+/// Writes an HTML table as `longtable` in the configured table style, using `\multicolumn`
+/// and `\multirow` for spanning cells.
+///
+/// Head rows (`<thead>`, or leading rows of `<th>` only) are repeated after page breaks.
+/// The `<caption>`, or else `caption`, captions the table. A table nested in a cell
+/// (`in_table`) becomes a plain `tabular`.
+pub fn write_table(
+    wr: &mut TexWriter<String>,
+    converter: &Converter,
+    node: &Handle,
+    in_table: bool,
+    caption: Option<Caption>,
+) {
+    let style = converter.table_style;
+    let mut rows = Vec::new();
+    collect_rows(converter, node, false, &mut rows);
+
+    if rows.is_empty() {
+        return;
+    }
+
+    // Rows can't span past the head or body they're in.
+    for i in 0..rows.len() {
+        let head = rows[i].head;
+        let left = rows[i..].iter().take_while(|row| row.head == head).count();
+        for cell in rows[i].cells.iter_mut() {
+            if cell.rowspan == 0 || cell.rowspan > left {
+                cell.rowspan = left;
+            }
+        }
+    }
+
+    // Without a <thead>, leading rows of header cells make up the head.
+    if !rows.iter().any(|row| row.head) {
+        for row in rows.iter_mut() {
+            if row.cells.is_empty() || !row.cells.iter().all(|cell| cell.header) {
+                break;
+            }
+            row.head = true;
+        }
+    }
+
+    // Rows still covered by a `rowspan` from above, per column: (remaining rows, colspan).
+    // Columns covered by the same cell after the first have a colspan of 0.
+    let mut covered: Vec<(usize, usize)> = Vec::new();
+    let mut lines = Vec::new();
+    let mut body_rows = 0;
+
+    for (i, row) in rows.iter().enumerate() {
+        let mut line = Vec::new();
+        let mut col = 0;
+        let mut cells = row.cells.iter();
+
+        loop {
+            // Leave room for cells spanning down from previous rows.
+            if col < covered.len() && covered[col].0 > 0 {
+                let span = covered[col].1.max(1);
+                for c in covered.iter_mut().skip(col).take(span) {
+                    c.0 -= 1;
+                }
+                line.push(span_cell(style, col, span, ""));
+                col += span;
+                continue;
+            }
+
+            let cell = match cells.next() {
+                Some(cell) => cell,
+                None if col < covered.len() && covered[col..].iter().any(|c| c.0 > 0) => {
+                    line.push(span_cell(style, col, 1, ""));
+                    col += 1;
+                    continue;
+                }
+                None => break,
+            };
+
+            if covered.len() < col + cell.colspan {
+                covered.resize(col + cell.colspan, (0, 0));
+            }
+            for (i, c) in covered.iter_mut().skip(col).take(cell.colspan).enumerate() {
+                *c = (cell.rowspan - 1, if i == 0 { cell.colspan } else { 0 });
+            }
+
+            let mut latex = if cell.header {
+                format!("\\textbf{{{}}}", cell.latex)
+            } else {
+                cell.latex.clone()
+            };
+            if cell.rowspan > 1 {
+                latex = format!("\\multirow{{{}}}{{*}}{{{}}}", cell.rowspan, latex);
+            }
+
+            line.push(span_cell(style, col, cell.colspan, &latex));
+            col += cell.colspan;
+        }
+
+        let mut rule = if i + 1 == rows.len() {
+            style.bottom_rule()
+        } else if row.head && !rows[i + 1].head {
+            style.head_rule()
+        } else if row.head {
+            r"\hline"
+        } else {
+            style.row_rule()
+        }
+        .to_string();
+
+        // Rules below the row skip columns that continue into the next one.
+        if covered.iter().any(|c| c.0 > 0) {
+            let mut clines = String::new();
+            let mut start = None;
+            for (i, c) in covered.iter().chain(std::iter::once(&(1, 0))).enumerate() {
+                match (c.0 == 0, start) {
+                    (true, None) => start = Some(i),
+                    (false, Some(s)) => {
+                        write!(clines, "\\cline{{{}-{}}}", s + 1, i).unwrap();
+                        start = None;
+                    }
+                    _ => {}
+                }
+            }
+            rule = rule.replace(r"\hline", &clines);
+        }
+
+        let color = style.row_color(row.head, body_rows);
+        if !row.head {
+            body_rows += 1;
+        }
+
+        lines.push((row.head, color, line, rule));
+    }
+
+    let columns = covered.len().max(1);
+
+    let mut cols = String::new();
+    for _ in 0..columns {
+        cols.push_str(style.column_rule());
+        match in_table {
+            true => cols.push('c'),
+            false => write!(cols, r"C{{{}\textwidth}} ", 1. / columns as f64).unwrap(),
+        }
+    }
+    cols.push_str(style.column_rule());
+
+    let mut captioned = false;
+    if in_table {
+        writeln!(wr, r"\begin{{tabular}}{{{}}}", cols).unwrap();
+    } else {
+        let table_start = [
+            r"\begingroup",
+            r"\setlength{\LTleft}{-20cm plus -1fill}",
+            r"\setlength{\LTright}{\LTleft}",
+        ];
+
+        wr.new_line().push_lines(table_start);
+        writeln!(wr, r"\begin{{longtable}}{{{}}}", cols).unwrap();
+
+        let caption = table_caption(node).or(caption);
+        if let Some(caption) = caption {
+            caption.write(wr, converter.chapter_path);
+            wr.back_slash().back_slash().new_line();
+            captioned = true;
+        }
+    }
+    let head_start = wr.buffer().len();
+    wr.push_str(style.top_rule()).new_line();
+
+    let has_head = lines.iter().any(|(head, ..)| *head);
+    let mut in_head = has_head && !in_table;
+
+    // The caption only goes on the first page, the head on every page.
+    let end_head = |wr: &mut TexWriter<String>| {
+        if captioned {
+            let head = wr.buffer()[head_start..].to_string();
+            wr.push_str(r"\endfirsthead").new_line().push_str(&head);
+        }
+        wr.push_str(r"\endhead").new_line();
+    };
+
+    for (head, color, line, rule) in &lines {
+        if in_head && !head {
+            end_head(wr);
+            in_head = false;
+        }
+
+        wr.push_str(color.unwrap_or_default())
+            .push_str(&line.join(" & "))
+            .push_str(r"\\")
+            .push_str(rule)
+            .new_line();
+    }
+
+    if in_head {
+        end_head(wr);
+    }
+
+    if in_table {
+        wr.push_str(r"\end{tabular}");
+    } else {
+        let table_end = [r"\end{longtable}", r"\endgroup"];
+        wr.push_lines(table_end).new_line();
+    }
+}
+
+/// Caption from a table's `<caption>` element.
+fn table_caption(node: &Handle) -> Option<Caption> {
+    node.children
+        .borrow()
+        .iter()
+        .find(|child| {
+            matches!(child.data, NodeData::Element { ref name, .. } if name.local.as_ref() == "caption")
+        })
+        .map(|child| {
+            let mut text = String::new();
+            collect_text(child, &mut text);
+            Caption::new(&text)
+        })
+}
+
+/// The table an HTML fragment consists of, if it's nothing else.
+pub fn lone_table(node: &Handle) -> Option<Handle> {
+    let children = node.children.borrow();
+    let mut elements = children.iter().filter(|child| match child.data {
+        NodeData::Text { ref contents } => !contents.borrow().trim().is_empty(),
+        NodeData::Element { .. } => true,
+        _ => false,
+    });
+
+    let child = elements.next()?;
+    if elements.next().is_some() {
+        return None;
+    }
+
+    match child.data {
+        NodeData::Element { ref name, .. } if name.local.as_ref() == "table" => Some(child.clone()),
+        NodeData::Element { ref name, .. } if name.local.as_ref() == "html" => lone_table(child),
+        _ => None,
+    }
+}
+
+/// Wraps a cell in `\multicolumn` if it spans several columns, keeping the rule left
+/// of the first column only, as the cell before has drawn it otherwise.
+fn span_cell(style: TableStyle, col: usize, colspan: usize, latex: &str) -> String {
+    if colspan > 1 {
+        let rule = style.column_rule();
+        let left = if col == 0 { rule } else { "" };
+        format!(
+            "\\multicolumn{{{}}}{{{}c{}}}{{{}}}",
+            colspan, left, rule, latex
+        )
+    } else {
+        latex.to_string()
+    }
+}
+
+/// Collects the rows of a table, descending into `<thead>`, `<tbody>` and `<tfoot>`.
+fn collect_rows(converter: &Converter, node: &Handle, head: bool, rows: &mut Vec<TableRow>) {
+    for child in node.children.borrow().iter() {
+        let name = match child.data {
+            NodeData::Element { ref name, .. } => name.local.as_ref(),
+            _ => continue,
+        };
+
+        match name {
+            "thead" => collect_rows(converter, child, true, rows),
+            "tbody" | "tfoot" => collect_rows(converter, child, head, rows),
+            "tr" => {
+                let mut cells = Vec::new();
+
+                for cell in child.children.borrow().iter() {
+                    let header = match cell.data {
+                        NodeData::Element { ref name, .. } if name.local.as_ref() == "th" => true,
+                        NodeData::Element { ref name, .. } if name.local.as_ref() == "td" => false,
+                        _ => continue,
+                    };
+
+                    // Clamped like browsers do, a `rowspan` of 0 reaching the end of the rows.
+                    let span = |key, span: std::ops::RangeInclusive<usize>| {
+                        attribute(cell, key)
+                            .and_then(|v| v.trim().parse::<usize>().ok())
+                            .unwrap_or(1)
+                            .clamp(*span.start(), *span.end())
+                    };
+
+                    let mut latex = TexWriter::new(String::new());
+                    write_children(&mut latex, converter, cell, true);
+
+                    cells.push(TableCell {
+                        latex: latex.into_buffer().trim().to_string(),
+                        colspan: span("colspan", 1..=1000),
+                        rowspan: span("rowspan", 0..=65534),
+                        header,
+                    });
+                }
+
+                rows.push(TableRow { cells, head });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Value of an element's attribute.
+fn attribute(node: &Handle, key: &str) -> Option<String> {
+    match node.data {
+        NodeData::Element { ref attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|a| a.name.local.as_ref() == key)
+            .map(|a| a.value.to_string()),
+        _ => None,
+    }
+}
+
+/// Writes the node's children between `start` and `end`.
+fn wrap(
+    wr: &mut TexWriter<String>,
+    converter: &Converter,
+    node: &Handle,
+    in_table: bool,
+    start: &str,
+    end: &str,
+) {
+    wr.push_str(start);
+    write_children(wr, converter, node, in_table);
+    wr.push_str(end);
+}
+
+/// Concatenates the raw text below a node.
+fn collect_text(node: &Handle, text: &mut String) {
+    if let NodeData::Text { ref contents } = node.data {
+        text.push_str(&contents.borrow());
+    }
+
+    for child in node.children.borrow().iter() {
+        collect_text(child, text);
+    }
+}
@@ -0,0 +1,157 @@
+use super::escape::{escape, EscapeMode};
+use super::writer::TexWriter;
+use super::{label, Converter};
+use regex::Regex;
+use std::fmt::Write;
+
+/// A marked index term.
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    /// Sort key and LaTeX text of the term and its subterms (`term!subterm`).
+    pub levels: Vec<(String, String)>,
+    /// Label placed where the term was marked.
+    pub label: String,
+}
+
+/// Term of an `<!-- index: term -->` comment.
+pub(crate) fn comment_term(html: &str) -> Option<String> {
+    let regex_comment = Regex::new(r"(?s)^\s*<!--\s*index:\s*(.*?)\s*-->\s*$").unwrap();
+
+    regex_comment.captures(html).map(|cap| cap[1].to_string())
+}
+
+/// Levels of a `term!subterm` mark, where each level may set its sort key as `key@text`.
+pub(crate) fn term_levels(term: &str) -> Vec<(String, String)> {
+    term.split('!')
+        .map(|level| match level.split_once('@') {
+            Some((key, text)) => (
+                key.trim().to_string(),
+                escape(text.trim(), EscapeMode::Text),
+            ),
+            None => (
+                level.trim().to_string(),
+                escape(level.trim(), EscapeMode::Text),
+            ),
+        })
+        .collect()
+}
+
+/// Levels of an inline code identifier, `None` for anything else.
+pub(crate) fn code_levels(code: &str) -> Option<Vec<(String, String)>> {
+    let regex_ident =
+        Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*((::|\.)[A-Za-z_][A-Za-z0-9_]*)*(\(\))?$").unwrap();
+
+    regex_ident.is_match(code).then(|| {
+        vec![(
+            code.to_string(),
+            format!(r"\texttt{{{}}}", escape(code, EscapeMode::Code)),
+        )]
+    })
+}
+
+/// Marks the current position for the index, if the converter collects one.
+pub(crate) fn write_mark(
+    wr: &mut TexWriter<String>,
+    converter: &Converter,
+    levels: Vec<(String, String)>,
+) {
+    let entries = match converter.index {
+        Some(entries) => entries,
+        None => return,
+    };
+    if levels.iter().all(|(key, _)| key.is_empty()) {
+        return;
+    }
+
+    let n = entries.borrow().len().to_string();
+    let label = format!("index:{}", label(converter.chapter_path, Some(n.as_str())));
+
+    // `makeindex` syntax, for templates that run it themselves.
+    let entry = levels
+        .iter()
+        .map(|(key, text)| format!("{}@{}", quote(&sort_key(key)), quote(text)))
+        .collect::<Vec<_>>()
+        .join("!");
+
+    write!(wr, r"\phantomsection\label{{{}}}\index{{{}}}", label, entry).unwrap();
+
+    entries.borrow_mut().push(IndexEntry { levels, label });
+}
+
+/// The index as a `theindex` environment, sorted here since Tectonic can't run `makeindex`.
+pub fn print_index(entries: &[&IndexEntry]) -> String {
+    let mut entries = entries.to_vec();
+    entries.sort_by_cached_key(|e| {
+        e.levels
+            .iter()
+            .map(|(key, text)| (sort_key(key).to_lowercase(), text.clone()))
+            .collect::<Vec<_>>()
+    });
+
+    let mut index = String::from("\\begin{theindex}\n\\addcontentsline{toc}{chapter}{\\indexname}");
+    // Marks on the same page as the one before are listed once.
+    index.push_str(concat!(
+        "\n\\def\\indexpage#1{\\edef\\indexpagenumber{\\getpagerefnumber{#1}}",
+        "\\ifx\\indexpagenumber\\indexlastpage\\else, \\pageref{#1}\\fi",
+        "\\let\\indexlastpage\\indexpagenumber}",
+    ));
+    let mut last: &[(String, String)] = &[];
+
+    let initial = |levels: &[(String, String)]| {
+        levels
+            .first()
+            .and_then(|(key, _)| sort_key(key).chars().next())
+            .map(|c| c.to_lowercase().to_string())
+    };
+
+    for entry in entries {
+        let levels = &entry.levels[..entry.levels.len().min(3)];
+
+        if levels == last {
+            write!(index, r"\indexpage{{{}}}", entry.label).unwrap();
+            continue;
+        }
+
+        let common = levels.iter().zip(last).take_while(|(a, b)| a == b).count();
+
+        // A little space between letters.
+        if common == 0 && !last.is_empty() && initial(levels) != initial(last) {
+            index.push_str("\n\\indexspace");
+        }
+
+        for (depth, (_, text)) in levels.iter().enumerate().skip(common) {
+            let item = ["item", "subitem", "subsubitem"][depth];
+            write!(index, "\n\\{} {}", item, text).unwrap();
+        }
+        write!(
+            index,
+            r"\let\indexlastpage\relax\indexpage{{{}}}",
+            entry.label
+        )
+        .unwrap();
+
+        last = levels;
+    }
+
+    index.push_str("\n\\end{theindex}\n");
+    index
+}
+
+/// Sort key without characters that would unbalance or break the `\index` argument.
+fn sort_key(key: &str) -> String {
+    key.chars()
+        .filter(|c| !matches!(c, '\\' | '{' | '}' | '%' | '#'))
+        .collect()
+}
+
+/// Quotes `makeindex`'s own special characters.
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '"' | '@' | '!' | '|') {
+            quoted.push('"');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
@@ -1,23 +1,43 @@
+mod bibliography;
+mod caption;
 mod converter;
+mod diagram;
+mod emoji;
+mod escape;
 mod events;
+mod extensions;
+mod glossary;
+mod html;
+mod index;
+mod script;
+mod table;
 mod writer;
 
-use crate::rcdom::NodeData;
-use crate::rcdom::RcDom;
+pub use bibliography::Bibliography;
+use caption::Caption;
 pub use converter::Converter;
+pub use diagram::{Diagrams, DIAGRAM_COMMANDS};
+pub use escape::{escape, escape_with, EscapeMode};
 use events::*;
-use html5ever::driver::ParseOpts;
-use html5ever::parse_document;
-use html5ever::tendril::TendrilSink;
-use inflector::cases::kebabcase::to_kebab_case;
-use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag};
+pub use extensions::Extension;
+use extensions::{parser_options, DEFAULT_EXTENSIONS};
+use glossary::GlossaryLinker;
+pub use glossary::{parse_glossary, print_glossary, GlossaryEntry};
+pub use index::{print_index, IndexEntry};
+use mdbook::utils::unique_id_from_content;
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Parser, Tag};
 use regex::Regex;
+use script::ScriptRuns;
+pub use script::{is_rtl, polyglossia_language, used_languages};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::default::Default;
 use std::fmt::Write;
 use std::fs;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::string::String;
+pub use table::TableStyle;
 use tiny_skia::Pixmap;
 use usvg::{fontdb, TreeParsing, TreeTextToPath};
 use walkdir::WalkDir;
@@ -29,105 +49,277 @@ pub fn markdown_to_tex(content: String) -> String {
     Converter::new(&content).run()
 }
 
+/// LaTeX sectioning commands, from the top level down.
+const SECTIONING: [&str; 6] = [
+    "chapter",
+    "section",
+    "subsection",
+    "subsubsection",
+    "paragraph",
+    "subparagraph",
+];
+
+/// Nesting depth of each list kind, as set with `\setlistdepth` in the template.
+const MAX_LIST_DEPTH: usize = 9;
+
+/// An open Markdown list.
+struct ListState {
+    env: &'static str,
+    /// Where list options go, unless the list is nested too deep for its own environment.
+    options_at: Option<usize>,
+    start: Option<u64>,
+    loose: bool,
+}
+
 /// Converts markdown string to tex string.
 fn convert(converter: &Converter) -> String {
     let mut writer = TexWriter::new(String::new());
 
     let mut header_value = String::new();
+    // Counts heading ids like mdBook does, to make them unique within the chapter.
+    let mut heading_ids = HashMap::new();
+    let mut chapter_labelled = false;
+    let mut heading_command = SECTIONING[0];
+    let mut heading_listed = false;
+    let mut numbered_heading = false;
     let mut table_buffer = TexWriter::new(String::new());
 
     let mut event_stack = Vec::new();
 
     let mut cells = 0;
+    let mut in_table = false;
+    let mut cell_start = 0;
+    let mut table_rows = 0;
+    let mut table_head_start = 0;
+    let mut table_caption: Option<Caption> = None;
+    // Caption of the open code block, and the length of source it takes up after the block.
+    let mut listing_caption: Option<(Caption, usize)> = None;
+    // Language of the open diagram block, where its LaTeX starts and where its source starts.
+    let mut diagram: Option<(String, usize, usize)> = None;
+    let style = converter.table_style;
+
+    let mut lists: Vec<ListState> = Vec::new();
+
+    // Inline content goes to the table while one is being built.
+    macro_rules! out {
+        () => {
+            if in_table {
+                &mut table_buffer
+            } else {
+                &mut writer
+            }
+        };
+    }
 
-    let options = Options::ENABLE_SMART_PUNCTUATION
-        | Options::ENABLE_STRIKETHROUGH
-        | Options::ENABLE_FOOTNOTES
-        | Options::ENABLE_TASKLISTS
-        | Options::ENABLE_TABLES;
+    let extensions = converter.extensions.unwrap_or(&DEFAULT_EXTENSIONS);
+    let options = parser_options(extensions);
 
     let parser = Parser::new_ext(converter.content, options);
 
+    // `^sup^`, and `~sub~` within words, which pulldown-cmark doesn't take for strikethrough.
+    let supsub: Vec<&str> = [
+        (Extension::Superscript, r"\^(?P<sup>[^\s^]+)\^"),
+        (Extension::Subscript, r"~(?P<sub>[^\s~]+)~"),
+    ]
+    .iter()
+    .filter(|(extension, _)| extensions.contains(extension))
+    .map(|(_, regex)| *regex)
+    .collect();
+    let regex_supsub = (!supsub.is_empty()).then(|| Regex::new(&supsub.join("|")).unwrap());
+
     let mut buffer = String::new();
+    // Code block info strings like `rust,ignore`.
+    let regex_info = Regex::new(r",.*").unwrap();
+    // "$$", "\[" and "\(" begin an equation, whose characters aren't replaced.
+    let regex_eq_start = Regex::new(r"(\$\$|\\\[|\\\()").unwrap();
+    let regex_eq_end = Regex::new(r"(\$\$|\\\]|\\\))").unwrap();
+
+    let regex_citation = Regex::new(bibliography::CITATION).unwrap();
+    let mut glossary = converter.glossary.and_then(GlossaryLinker::new);
+    let scripts = converter.language.map(ScriptRuns::new);
+    let mut in_link = false;
+    // Label of a figure, table or listing the open link refers to, and where its text starts.
+    let mut reference: Option<(String, usize)> = None;
+    let regex_alert = Regex::new(r"^\s*>\s*\[!(\w+)\][ \t]*(\r?\n|$)").unwrap();
+    let regex_title = Regex::new(r#"title="([^"]*)""#).unwrap();
+
+    // Environments closing the currently open blockquotes.
+    let mut quote_envs = Vec::new();
+
+    let mut admonition: Option<(String, Option<String>)> = None;
+    let mut admonition_buffer = String::new();
+
+    let mut paragraph_start = 0;
+
+    let mut pending_html = html::HtmlAccumulator::new();
 
     writer.new_line();
 
-    for event in parser {
+    // Source offset up to which the text of an alert marker ("> [!NOTE]") is skipped.
+    let mut skip_until = 0;
+    // Source offset up to which a `Table: caption` paragraph is skipped entirely.
+    let mut caption_until = 0;
+
+    let mut events: VecDeque<_> = match converter.events {
+        Some(events) => events.iter().cloned().collect(),
+        None => merge_text(parser.into_offset_iter()).into(),
+    };
+
+    loop {
+        let (event, range) = match events.pop_front() {
+            Some(event) => event,
+            // HTML still open at the end of the chapter doesn't take the Markdown in it along.
+            None if pending_html.is_open() => {
+                let (dom, rest) = pending_html.abandon(converter);
+                html::write_node(out!(), converter, &dom.document, in_table);
+                events.extend(rest);
+                continue;
+            }
+            None => break,
+        };
+
+        if range.start < skip_until && matches!(event, Event::Text(_) | Event::SoftBreak) {
+            continue;
+        }
+        if range.end <= caption_until {
+            continue;
+        }
+
+        // Unclosed or stray tags don't swallow the Markdown after them: it's converted
+        // again from where their HTML ended.
+        if pending_html.is_open() && pending_html.is_interrupted_by(&event) {
+            let (dom, rest) = pending_html.abandon(converter);
+            html::write_node(out!(), converter, &dom.document, in_table);
+
+            events.push_front((event, range));
+            for event in rest.into_iter().rev() {
+                events.push_front(event);
+            }
+            continue;
+        }
+
+        if let (false, Event::Html(comment)) = (pending_html.is_open(), &event) {
+            if let Some(caption) = table::comment_caption(comment) {
+                table_caption = Some(caption);
+                continue;
+            }
+            // Index marks are dropped from headings.
+            if let Some(term) = index::comment_term(comment) {
+                if !event_stack.contains(&EventType::Header) {
+                    index::write_mark(out!(), converter, index::term_levels(&term));
+                }
+                continue;
+            }
+        }
+
+        // HTML is parsed once all its tags are closed, including any Markdown in between.
+        if pending_html.is_open() || matches!(event, Event::Html(_)) {
+            if pending_html.push(event, range) {
+                let dom = pending_html.finish(converter);
+
+                // A `<!-- caption: ... -->` comment captions an HTML table like a Markdown one.
+                match html::lone_table(&dom.document).filter(|_| table_caption.is_some()) {
+                    Some(table) => {
+                        let caption = table_caption.take();
+                        html::write_table(out!(), converter, &table, in_table, caption);
+                    }
+                    None => html::write_node(out!(), converter, &dom.document, in_table),
+                }
+            }
+            continue;
+        }
+
         match event {
-            Event::Start(Tag::Heading(level, _, _)) => {
+            Event::Start(Tag::Heading(level, _, classes)) => {
                 let last_ev = event_stack.last().copied().unwrap_or_default();
 
                 event_stack.push(EventType::Header);
+                header_value.clear();
+
+                // Sub-chapters start deeper, so their headings shift down as well.
+                let depth = converter.section_number.map_or(1, |n| n.len().max(1));
+                heading_command = match converter.in_admonition {
+                    // Headings in a box are only run-in titles.
+                    true => "paragraph",
+                    false => SECTIONING[(depth - 1 + level as usize - 1).min(SECTIONING.len() - 1)],
+                };
 
-                match level {
-                    HeadingLevel::H1 => {
-                        if converter.chap_offset == 0 {
-                            writer.push_str(r"\chapter*{")
-                        } else {
-                            writer.push_str(r"\chapter{")
-                        }
-                    }
-                    HeadingLevel::H2 => {
-                        if converter.chap_offset == 0 {
-                            writer.push_str(r"\section*{")
-                        } else {
-                            writer.push_str(r"\section{")
-                        }
-                    }
-                    HeadingLevel::H3 => {
-                        if converter.chap_offset == 0 {
-                            writer.push_str(r"\subsection*{")
-                        } else {
-                            writer.push_str(r"\subsection{")
-                        }
-                    }
-                    HeadingLevel::H4 => {
-                        if converter.chap_offset == 0 {
-                            writer.push_str(r"\subsubsection*{")
+                // Only the chapter's first H1 takes its number, later ones would repeat it.
+                let repeated_h1 = level == HeadingLevel::H1 && numbered_heading;
+
+                // Reproduce mdBook's section number instead of LaTeX's own count.
+                if let (Some(number), HeadingLevel::H1, false) =
+                    (converter.section_number, level, numbered_heading)
+                {
+                    for (i, n) in number.iter().enumerate().take(SECTIONING.len()) {
+                        let n = if i + 1 == number.len() {
+                            n.saturating_sub(1)
                         } else {
-                            writer.push_str(r"\subsubsection{")
-                        }
-                    }
-                    HeadingLevel::H5 => {
-                        // https://tex.stackexchange.com/questions/169830/pdflatex-raise-error-when-paragraph-inside-quote-environment
-                        if matches!(last_ev, EventType::BlockQuote) {
-                            writer.push_str(r"\mbox{} %").new_line();
-                        }
-                        writer.push_str(r"\paragraph{")
+                            *n
+                        };
+                        writeln!(writer, "\\setcounter{{{}}}{{{}}}", SECTIONING[i], n).unwrap();
                     }
-                    HeadingLevel::H6 => {
-                        // https://tex.stackexchange.com/questions/169830/pdflatex-raise-error-when-paragraph-inside-quote-environment
-                        if matches!(last_ev, EventType::BlockQuote) {
-                            writer.push_str(r"\mbox{} %").new_line();
-                        }
-                        writer.push_str(r"\subparagraph{")
+                    numbered_heading = true;
+                }
+
+                // Pandoc's `{.unnumbered}` heading attribute.
+                let unnumbered =
+                    converter.chap_offset == 0 || classes.contains(&"unnumbered") || repeated_h1;
+                // Like in mdBook, only chapters are numbered: numbered headings within them
+                // would clash with their sub-chapters' numbers. They're still in the contents.
+                let heading_starred = unnumbered || level != HeadingLevel::H1;
+                heading_listed = !converter.in_admonition
+                    && ((level == HeadingLevel::H1 && unnumbered)
+                        || (level != HeadingLevel::H1 && !unnumbered));
+
+                if matches!(heading_command, "paragraph" | "subparagraph") {
+                    // https://tex.stackexchange.com/questions/169830/pdflatex-raise-error-when-paragraph-inside-quote-environment
+                    if matches!(last_ev, EventType::BlockQuote) {
+                        writer.push_str(r"\mbox{} %").new_line();
                     }
-                };
+                    write!(writer, "\\{}{{", heading_command).unwrap();
+                } else if heading_starred {
+                    write!(writer, "\\{}*{{", heading_command).unwrap();
+                } else {
+                    write!(writer, "\\{}{{", heading_command).unwrap();
+                }
             }
-            Event::End(Tag::Heading(level, _, _)) => {
+            Event::End(Tag::Heading(level, id, _)) => {
+                writer.push('}').new_line();
+
+                // The chapter itself can be linked to as well.
+                if let (Some(chapter), HeadingLevel::H1, false) = (
+                    converter.chapter_path,
+                    level,
+                    chapter_labelled || converter.in_admonition,
+                ) {
+                    writeln!(writer, "\\label{{{}}}", label(Some(chapter), None)).unwrap();
+                    chapter_labelled = true;
+                }
+
+                // Same id as in the HTML book, or the explicit `{#id}` heading attribute.
+                let id = match id {
+                    Some(id) => id.to_string(),
+                    None => unique_id_from_content(&header_value, &mut heading_ids),
+                };
                 writeln!(
                     writer,
-                    "}}\n\\label{{{}}}\n\\label{{{}}}",
-                    header_value,
-                    to_kebab_case(&header_value)
+                    "\\label{{{}}}",
+                    label(converter.chapter_path, Some(id.as_str()))
                 )
                 .unwrap();
 
-                if converter.chap_offset == 0 {
-                    match level {
-                        HeadingLevel::H1 => {
-                            writeln!(
-                                writer,
-                                "\\addcontentsline{{toc}}{{chapter}}{{{}}}",
-                                header_value,
-                            )
-                            .unwrap();
-                        }
-                        _ => {}
-                    }
+                if heading_listed {
+                    writeln!(
+                        writer,
+                        "\\addcontentsline{{toc}}{{{}}}{{{}}}",
+                        heading_command,
+                        escape(&header_value, EscapeMode::Text),
+                    )
+                    .unwrap();
                 }
 
-                if level == HeadingLevel::H5 || level == HeadingLevel::H6 {
+                if matches!(heading_command, "paragraph" | "subparagraph") {
                     writer.push_str(r"\mbox{}\\").new_line();
                 }
 
@@ -135,52 +327,143 @@ fn convert(converter: &Converter) -> String {
             }
             Event::Start(Tag::Emphasis) => {
                 event_stack.push(EventType::Emphasis);
-                writer.push_str(r"\emph{");
+                out!().push_str(r"\emph{");
             }
             Event::End(Tag::Emphasis) => {
-                writer.push('}');
+                out!().push('}');
                 event_stack.pop();
             }
 
             Event::Start(Tag::Strong) => {
                 event_stack.push(EventType::Strong);
-                writer.push_str(r"\textbf{");
+                out!().push_str(r"\textbf{");
             }
             Event::End(Tag::Strong) => {
-                writer.push('}');
+                out!().push('}');
                 event_stack.pop();
             }
 
             Event::Start(Tag::BlockQuote) => {
                 event_stack.push(EventType::BlockQuote);
-                writer.push_str(r"\begin{shadedquotation}");
+
+                // GitHub style alert (e.g. "> [!NOTE]").
+                let alert = converter.admonitions.and_then(|colors| {
+                    let caps = regex_alert.captures(&converter.content[range.clone()])?;
+                    let kind = caps[1].to_lowercase();
+                    let marker_end = range.start + caps.get(0).unwrap().end();
+                    colors.contains_key(&kind).then_some((kind, marker_end))
+                });
+
+                match alert {
+                    Some((kind, marker_end)) => {
+                        skip_until = marker_end;
+                        begin_admonition(&mut writer, &kind, None);
+                        quote_envs.push("admonition");
+                    }
+                    None => {
+                        writer.push_str(r"\begin{shadedquotation}").new_line();
+                        quote_envs.push("shadedquotation");
+                    }
+                }
             }
             Event::End(Tag::BlockQuote) => {
-                writer.push_str(r"\end{shadedquotation}");
+                let env = quote_envs.pop().unwrap_or("shadedquotation");
+                writeln!(writer, "\\end{{{}}}", env).unwrap();
                 event_stack.pop();
             }
 
-            Event::Start(Tag::List(None)) => {
-                writer.push_str(r"\begin{itemize}").new_line();
-            }
-            Event::End(Tag::List(None)) => {
-                writer.push_str(r"\end{itemize}").new_line();
-            }
+            Event::Start(Tag::List(start)) => {
+                let env = if start.is_some() {
+                    "enumerate"
+                } else {
+                    "itemize"
+                };
+                let depth = lists.iter().filter(|l| l.env == env).count();
+
+                // Deeper lists carry on inside the deepest one LaTeX allows.
+                if depth >= MAX_LIST_DEPTH {
+                    lists.push(ListState {
+                        env,
+                        options_at: None,
+                        start,
+                        loose: false,
+                    });
+                    continue;
+                }
 
-            Event::Start(Tag::List(Some(_))) => {
-                writer.push_str(r"\begin{enumerate}").new_line();
+                write!(writer, "\\begin{{{}}}", env).unwrap();
+                lists.push(ListState {
+                    env,
+                    options_at: Some(writer.buffer().len()),
+                    start,
+                    loose: false,
+                });
+                writer.new_line();
             }
-            Event::End(Tag::List(Some(_))) => {
-                writer.push_str(r"\end{enumerate}").new_line();
+            Event::End(Tag::List(_)) => {
+                let list = lists.pop().unwrap();
+
+                if let Some(at) = list.options_at {
+                    writeln!(writer, "\\end{{{}}}", list.env).unwrap();
+
+                    let start = list.start.filter(|&n| n != 1);
+                    if converter.enumitem {
+                        let mut options = Vec::new();
+                        if let Some(start) = start {
+                            options.push(format!("start={}", start));
+                        }
+                        if !list.loose {
+                            options.push("noitemsep".to_string());
+                        }
+                        if !options.is_empty() {
+                            writer
+                                .buffer()
+                                .insert_str(at, &format!("[{}]", options.join(",")));
+                        }
+                    } else if let Some(start) = start {
+                        // Plain LaTeX lists only start elsewhere through their counter.
+                        let depth = lists.iter().filter(|l| l.env == list.env).count();
+                        if let Some(counter) = ["enumi", "enumii", "enumiii", "enumiv"].get(depth) {
+                            let setcounter =
+                                format!("\n\\setcounter{{{}}}{{{}}}", counter, start as i64 - 1);
+                            writer.buffer().insert_str(at, &setcounter);
+                        }
+                    }
+                }
             }
 
             Event::Start(Tag::Paragraph) => {
+                table_caption = None;
+                // Paragraphs inside items only appear in loose lists.
+                if let Some(list) = lists.last_mut() {
+                    list.loose = true;
+                }
+                if let Some(caption) = table::paragraph_caption(
+                    &converter.content[range.clone()],
+                    &converter.content[range.end..],
+                ) {
+                    table_caption = Some(caption);
+                    caption_until = range.end;
+                    continue;
+                }
+
                 writer.new_line();
+                paragraph_start = writer.buffer().len();
             }
 
             Event::End(Tag::Paragraph) => {
                 let last_ev = event_stack.last().copied().unwrap_or_default();
 
+                if extensions.contains(&Extension::DefinitionLists) {
+                    let source = &converter.content[range];
+                    if let Some(list) = definition_list(source, &writer.buffer()[paragraph_start..])
+                    {
+                        writer.buffer().truncate(paragraph_start);
+                        writer.push_str(&list);
+                        continue;
+                    }
+                }
+
                 // ~ adds a space to prevent
                 // "There's no line here to end" error on empty lines.
                 match last_ev {
@@ -189,13 +472,40 @@ fn convert(converter: &Converter) -> String {
                 };
             }
 
+            Event::Start(Tag::Strikethrough) => {
+                // Both `~~text~~` and `~text~` arrive as strikethrough, subscripts can't have spaces.
+                let source = &converter.content[range];
+                let subscript = !source.starts_with("~~")
+                    && !source.trim_matches('~').contains(char::is_whitespace);
+
+                if extensions.contains(&Extension::Subscript) && subscript {
+                    out!().push_str(r"\textsubscript{");
+                } else {
+                    out!().push_str(r"\sout{");
+                }
+            }
+            Event::End(Tag::Strikethrough) => {
+                out!().push('}');
+            }
+
             Event::Start(Tag::Link(_, url, _)) => {
-                // URL link (e.g. "https://nasa.gov/my/cool/figure.png")
-                if url.starts_with("http") {
-                    write!(writer, r"\href{{{}}}{{", url).unwrap();
+                in_link = true;
+                // URL link (e.g. "https://nasa.gov/my/cool/figure.png", "mailto:me@example.com")
+                if url.starts_with("http") || has_scheme(&url) {
+                    write!(out!(), r"\href{{{}}}{{", escape(&url, EscapeMode::Url)).unwrap();
+                // local link resolved against the online book
+                } else if let Some(target) = online_url(converter.link_base, &url) {
+                    write!(out!(), r"\href{{{}}}{{", escape(&target, EscapeMode::Url)).unwrap();
+                // link to a chapter or heading of the book
+                } else if let Some(label) = local_label(converter.chapter_path, &url) {
+                    if caption::is_reference(&url) {
+                        reference = Some((label, out!().buffer().len()));
+                    } else {
+                        write!(out!(), r"\hyperref[{}]{{", label).unwrap();
+                    }
                 // local link (e.g. "my/cool/figure.png")
                 } else {
-                    writer.push_str(r"\hyperref[");
+                    out!().push_str(r"\hyperref[");
                     let mut found = false;
 
                     // iterate through `src` directory to find the resource.
@@ -214,57 +524,86 @@ fn convert(converter: &Converter) -> String {
                     }
 
                     if !found {
-                        writer.push_str(&*url.replace("#", ""));
+                        out!().push_str(&escape(&url.replace("#", ""), EscapeMode::Label));
                     }
 
-                    writer.push_str("]{");
+                    out!().push_str("]{");
                 }
             }
 
             Event::End(Tag::Link(_, _, _)) => {
-                writer.push('}');
+                in_link = false;
+                match reference.take() {
+                    // `[see](#fig:arch)` reads "see Figure 3.2", an empty link just "Figure 3.2".
+                    Some((label, start)) => {
+                        if out!().buffer().len() > start {
+                            out!().push('~');
+                        }
+                        write!(out!(), r"\autoref{{{}}}", label).unwrap();
+                    }
+                    None => {
+                        out!().push('}');
+                    }
+                }
             }
 
             Event::Start(Tag::Table(_)) => {
                 event_stack.push(EventType::Table);
+                in_table = true;
 
                 let table_start = [
                     r"\begingroup",
                     r"\setlength{\LTleft}{-20cm plus -1fill}",
                     r"\setlength{\LTright}{\LTleft}",
                     r"\begin{longtable}{!!!}",
-                    r"\hline",
                 ];
 
-                table_buffer.new_line().push_lines(table_start).new_line();
+                table_buffer.new_line().push_lines(table_start);
+
+                if let Some(caption) = &table_caption {
+                    caption.write(&mut table_buffer, converter.chapter_path);
+                    table_buffer.back_slash().back_slash().new_line();
+                }
+
+                table_head_start = table_buffer.buffer().len();
+                table_buffer.push_str(style.top_rule()).new_line();
             }
 
             Event::End(Tag::Table(_)) => {
-                let table_end = [
-                    r"\arrayrulecolor{black}\hline",
-                    r"\end{longtable}",
-                    r"\endgroup",
-                ];
+                let table_end = [style.bottom_rule(), r"\end{longtable}", r"\endgroup"];
 
                 table_buffer.push_lines(table_end).new_line();
 
                 let mut cols = String::new();
                 for _i in 0..cells {
-                    write!(cols, r"|C{{{width}\textwidth}} ", width = 1. / cells as f64).unwrap();
+                    write!(
+                        cols,
+                        r"{rule}C{{{width}\textwidth}} ",
+                        rule = style.column_rule(),
+                        width = 1. / cells as f64
+                    )
+                    .unwrap();
                 }
 
-                cols.push_str("|");
+                cols.push_str(style.column_rule());
 
                 writer.push_str(&table_buffer.buffer().replace("!!!", &cols));
                 table_buffer.buffer().clear();
 
                 cells = 0;
+                table_rows = 0;
+                table_caption = None;
 
+                in_table = false;
                 event_stack.pop();
             }
 
             Event::Start(Tag::TableHead) => {
                 event_stack.push(EventType::TableHead);
+
+                if let Some(color) = style.row_color(true, 0) {
+                    table_buffer.push_str(color);
+                }
             }
 
             Event::End(Tag::TableHead) => {
@@ -276,9 +615,19 @@ fn convert(converter: &Converter) -> String {
                     .back_slash()
                     .back_slash()
                     .new_line()
-                    .push_str(r"\hline")
+                    .push_str(style.head_rule())
                     .new_line();
 
+                // The caption only goes on the first page, the header on every page.
+                if table_caption.is_some() {
+                    let head = table_buffer.buffer()[table_head_start..].to_string();
+                    table_buffer
+                        .push_str(r"\endfirsthead")
+                        .new_line()
+                        .push_str(&head);
+                }
+                table_buffer.push_str(r"\endhead").new_line();
+
                 event_stack.pop();
             }
 
@@ -286,9 +635,30 @@ fn convert(converter: &Converter) -> String {
                 if matches!(event_stack.last(), Some(EventType::TableHead)) {
                     table_buffer.push_str(r"\bfseries{");
                 }
+                cell_start = table_buffer.buffer().len();
             }
 
             Event::End(Tag::TableCell) => {
+                // Lines and lists need their own box inside a cell.
+                let cell = table_buffer.buffer().split_off(cell_start);
+                if cell.contains(r"\begin{itemize}") || cell.contains(r"\begin{enumerate}") {
+                    write!(
+                        table_buffer,
+                        "\\begin{{minipage}}[t]{{\\linewidth}}\\raggedright {}\\end{{minipage}}",
+                        cell
+                    )
+                    .unwrap();
+                } else if cell.contains(r"\newline{}") {
+                    write!(
+                        table_buffer,
+                        "\\makecell{{{}}}",
+                        cell.trim().replace(r"\newline{}", r"\\")
+                    )
+                    .unwrap();
+                } else {
+                    table_buffer.push_str(&cell);
+                }
+
                 if matches!(event_stack.last(), Some(EventType::TableHead)) {
                     table_buffer.push('}');
                     cells += 1;
@@ -297,7 +667,12 @@ fn convert(converter: &Converter) -> String {
                 table_buffer.push_str(" & ");
             }
 
-            Event::Start(Tag::TableRow) => {}
+            Event::Start(Tag::TableRow) => {
+                if let Some(color) = style.row_color(false, table_rows) {
+                    table_buffer.push_str(color);
+                }
+                table_rows += 1;
+            }
 
             Event::End(Tag::TableRow) => {
                 let limit = table_buffer.buffer().len() - 2;
@@ -305,54 +680,28 @@ fn convert(converter: &Converter) -> String {
                 table_buffer.buffer().truncate(limit);
 
                 table_buffer
-                    .push_str(r"\\\arrayrulecolor{lightgray}\hline")
+                    .back_slash()
+                    .back_slash()
+                    .push_str(style.row_rule())
                     .new_line();
             }
 
-            Event::Start(Tag::Image(_, path, _title)) => {
-                let mut assets_path = converter
-                    .assets
-                    .map(|p| p.to_path_buf())
-                    .unwrap_or_default()
-                    .join(path.as_ref());
-
-                let mut path = PathBuf::from_str(path.as_ref()).unwrap();
-
-                // if image path ends with ".svg", run it through
-                // svg2png to convert to png file.
-                if path.extension().unwrap() == "svg" {
-                    let img = svg2png(&assets_path);
-
-                    path.set_extension("png");
-                    let path = path
-                        .strip_prefix("../..")
-                        .map(Path::to_path_buf)
-                        .unwrap_or(path);
-
-                    let dest_path = converter
-                        .dest
-                        .map(|p| p.to_path_buf())
-                        .unwrap_or_default()
-                        .join(path.clone());
-
-                    // create output directories.
-                    let _ = fs::create_dir_all(dest_path.parent().unwrap());
-
-                    img.save_png(&dest_path).unwrap();
-                    assets_path = dest_path;
+            Event::Start(Tag::Image(_, path, title)) => {
+                let caption = match in_table {
+                    true => None,
+                    false => caption::image_caption(
+                        &converter.content[..range.start],
+                        &title,
+                        &converter.content[range.end..],
+                    ),
+                };
+                // The alt text and any italic caption line aren't written on their own.
+                if let Some((_, len)) = &caption {
+                    caption_until = range.end + len;
                 }
 
-                writer
-                    .push_str(r"\begin{figure}")
-                    .new_line()
-                    .push_str(r"\centering")
-                    .new_line()
-                    .push_str(r"\includegraphics[width=\textwidth]{")
-                    .push_str(assets_path.to_string_lossy().as_ref())
-                    .push('}')
-                    .new_line()
-                    .push_str(r"\end{figure}")
-                    .new_line();
+                let caption = caption.as_ref().map(|(caption, _)| caption);
+                write_image(out!(), converter, &path, in_table, caption);
             }
 
             Event::Start(Tag::Item) => {
@@ -363,171 +712,164 @@ fn convert(converter: &Converter) -> String {
             }
 
             Event::Start(Tag::CodeBlock(lang)) => {
-                let re = Regex::new(r",.*").unwrap();
+                let listing_start = writer.buffer().len();
+                let mut diagram_lang = None;
 
                 match lang {
                     CodeBlockKind::Indented => {
                         writer.push_str(r"\begin{minted}{text}").new_line();
+                        // Unlike fenced blocks, indented ones take their last line break along.
+                        let end = converter.content[..range.end]
+                            .trim_end_matches(['\r', '\n'])
+                            .len();
+                        listing_caption = caption::code_caption("", &converter.content[end..])
+                            .map(|(caption, len)| (caption, len - (range.end - end)));
+                    }
+                    // mdbook-admonish block (e.g. "```admonish warning title=\"Careful\"").
+                    CodeBlockKind::Fenced(info)
+                        if converter.admonitions.is_some() && info.starts_with("admonish") =>
+                    {
+                        let colors = converter.admonitions.unwrap();
+                        let kind = info["admonish".len()..]
+                            .split_whitespace()
+                            .next()
+                            .filter(|kind| !kind.contains('='))
+                            .filter(|kind| colors.contains_key(*kind))
+                            .unwrap_or("note");
+                        let title = regex_title.captures(&info).map(|c| c[1].to_string());
+
+                        admonition = Some((kind.to_string(), title));
+                        event_stack.push(EventType::Admonition);
+                        continue;
                     }
-                    CodeBlockKind::Fenced(lang) => {
+                    CodeBlockKind::Fenced(info) => {
                         writer.push_str(r"\begin{minted}{");
-                        let lang = re.replace(&lang, "");
-                        let lang = lang.split_whitespace().next().unwrap_or_else(|| "text");
+                        let lang = regex_info.replace(&info, "");
+                        let lang = lang
+                            .split_whitespace()
+                            .next()
+                            .filter(|lang| !lang.starts_with('{') && !lang.contains('='))
+                            .unwrap_or("text");
 
                         writeln!(writer, "{}}}", lang).unwrap();
+                        listing_caption =
+                            caption::code_caption(&info, &converter.content[range.end..]);
+
+                        if converter.diagrams.filter(|d| d.renders(lang)).is_some() {
+                            diagram_lang = Some(lang.to_string());
+                        }
                     }
                 }
 
+                // Captioned code is numbered as a listing.
+                if listing_caption.is_some() {
+                    writer
+                        .buffer()
+                        .insert_str(listing_start, "\\begin{listing}[H]\n");
+                }
+                diagram = diagram_lang.map(|lang| (lang, listing_start, writer.buffer().len()));
+
                 event_stack.push(EventType::Code);
             }
 
             Event::End(Tag::CodeBlock(_)) => {
-                writer.push_str(r"\end{minted}").new_line();
+                // The source stays as a code listing if the diagram can't be rendered.
+                let rendered = diagram.take().and_then(|(lang, start, source)| {
+                    match converter
+                        .diagrams?
+                        .render(&lang, &writer.buffer()[source..])
+                    {
+                        Ok(svg) => Some((svg, start)),
+                        Err(e) => {
+                            eprintln!("Unable to render {} diagram: {}", lang, e);
+                            None
+                        }
+                    }
+                });
+
+                if let Some((kind, title)) = admonition.take() {
+                    // The body of an admonition is Markdown itself.
+                    let inner = Converter {
+                        content: &admonition_buffer,
+                        template: None,
+                        section_number: None,
+                        in_admonition: true,
+                        ..*converter
+                    };
+
+                    begin_admonition(&mut writer, &kind, title.as_deref());
+                    writer
+                        .push_str(&convert(&inner))
+                        .push_str(r"\end{admonition}")
+                        .new_line();
+
+                    admonition_buffer.clear();
+                } else if let Some((svg, start)) = rendered {
+                    writer.buffer().truncate(start);
+
+                    let caption = listing_caption.take();
+                    write_image(
+                        &mut writer,
+                        converter,
+                        &svg.to_string_lossy(),
+                        false,
+                        caption.as_ref().map(|(caption, _)| caption),
+                    );
+                    if let Some((_, len)) = caption {
+                        caption_until = range.end + len;
+                    }
+                } else {
+                    writer.push_str(r"\end{minted}").new_line();
+
+                    if let Some((caption, len)) = listing_caption.take() {
+                        caption.write(&mut writer, converter.chapter_path);
+                        writer.new_line().push_str(r"\end{listing}").new_line();
+                        caption_until = range.end + len;
+                    }
+                }
 
                 event_stack.pop();
             }
 
             Event::Code(t) => {
-                let wr = if event_stack
-                    .iter()
-                    .any(|ev| matches!(ev, EventType::Table | EventType::TableHead))
-                {
-                    &mut table_buffer
-                } else {
-                    &mut writer
-                };
-
                 if event_stack.contains(&EventType::Header) {
-                    wr.push_str(r"\texttt{").escape_str(&t).push('}');
-                } else {
-                    let mut code = String::with_capacity(t.len());
-
-                    if let Some((es, ee)) = converter.code_utf8_escape {
-                        for c in t.chars() {
-                            if c.is_ascii() {
-                                code.push(c);
-                            } else {
-                                write!(code, "{}{}{}", es, c, ee).unwrap();
-                            }
-                        }
-                    } else {
-                        code.push_str(&*t);
-                    }
-
-                    let delims = ['|', '!', '?', '+', '@'];
-
-                    let delim = delims
-                        .iter()
-                        .find(|c| !code.contains(**c))
-                        .expect("Failed to find listing delmeter");
-
-                    write!(wr, r"\lstinline{}{}{}", delim, code, delim).unwrap();
+                    header_value.push_str(&t);
                 }
-            }
-            Event::Html(t) => {
-                let dom = parse_document(RcDom::default(), ParseOpts::default())
-                    .from_utf8()
-                    .read_from(&mut t.as_bytes())
-                    .unwrap();
+                write_code(out!(), &t);
 
-                let dom_children = &dom.document.children.borrow().to_owned();
-
-                if dom_children.len() > 0
-                    && matches!(dom_children[0].data, NodeData::Element { .. })
-                {
-                    let html_children = &dom_children[0].children.borrow().to_owned();
-
-                    if html_children.len() > 1 {
-                        let body_children = &html_children[1].children.borrow().to_owned();
-
-                        if body_children.len() > 0 {
-                            match &body_children[0].data {
-                                NodeData::Element { name, attrs, .. } => {
-                                    match name.local.as_ref() {
-                                        "img" => {
-                                            for attr in attrs.borrow().to_owned() {
-                                                match attr.name.local.as_ref() {
-                                                    "src" => {
-                                                        let src_path = attr.value.to_string();
-
-                                                        let mut assets_path = converter
-                                                            .assets
-                                                            .map(|p| p.to_path_buf())
-                                                            .unwrap_or_default()
-                                                            .join(src_path.clone());
-
-                                                        let mut path =
-                                                            PathBuf::from_str(&src_path).unwrap();
-
-                                                        // if image path ends with ".svg", run it through
-                                                        // svg2png to convert to png file.
-                                                        if path.extension().unwrap() == "svg" {
-                                                            let img = svg2png(&assets_path);
-
-                                                            path.set_extension("png");
-                                                            let path = path
-                                                                .strip_prefix("../..")
-                                                                .map(Path::to_path_buf)
-                                                                .unwrap_or(path);
-
-                                                            let dest_path = converter
-                                                                .dest
-                                                                .map(|p| p.to_path_buf())
-                                                                .unwrap_or_default()
-                                                                .join(path.clone());
-
-                                                            // create output directories.
-                                                            let _ = fs::create_dir_all(
-                                                                dest_path.parent().unwrap(),
-                                                            );
-
-                                                            img.save_png(&dest_path).unwrap();
-                                                            assets_path = dest_path;
-                                                        }
-
-                                                        writer
-                                                            .push_str(r"\begin{figure}")
-                                                            .new_line()
-                                                            .push_str(r"\centering")
-                                                            .new_line()
-                                                            .push_str(r"\includegraphics[width=\textwidth]{")
-                                                            .push_str(assets_path.to_string_lossy().as_ref())
-                                                            .push('}')
-                                                            .new_line()
-                                                            .push_str(r"\end{figure}")
-                                                            .new_line();
-                                                    }
-                                                    _ => {}
-                                                }
-                                            }
-                                        }
-                                        _ => {}
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
+                if converter.index_code && !event_stack.contains(&EventType::Header) {
+                    if let Some(levels) = index::code_levels(&t) {
+                        index::write_mark(out!(), converter, levels);
                     }
                 }
             }
             Event::Text(t) => {
-                // if "$$", "\[", "\(" are encountered, then begin equation
-                // and don't replace any characters.
-
-                let regex_eq_start = Regex::new(r"(\$\$|\\\[|\\\()").unwrap();
-                let regex_eq_end = Regex::new(r"(\$\$|\\\]|\\\))").unwrap();
+                let in_header = event_stack.contains(&EventType::Header);
+                // Glossary terms aren't linked where a link can't go.
+                let mut linker = match in_header || in_link {
+                    true => None,
+                    false => glossary.as_mut(),
+                };
 
-                buffer.clear();
-                buffer.push_str(&t.to_string());
+                let mut on_text = |wr: &mut TexWriter<String>, text: &str| {
+                    buffer.clear();
+                    buffer.push_str(text);
 
-                let mut on_text = |wr: &mut TexWriter<String>| {
                     // TODO more elegant way to do ordered `replace`s (structs?).
                     while !buffer.is_empty() {
                         if let Some(m) = regex_eq_start.find(&buffer) {
                             let end = m.end();
                             let start = m.start();
 
-                            wr.escape_str(&buffer[..start]).push_str(r"\[");
+                            write_prose(
+                                wr,
+                                converter,
+                                &buffer[..start],
+                                regex_supsub.as_ref(),
+                                scripts.as_ref(),
+                                linker.as_deref_mut(),
+                            );
+                            wr.push_str(r"\[");
                             buffer.drain(..end);
 
                             let m = regex_eq_end
@@ -541,31 +883,65 @@ fn convert(converter: &Converter) -> String {
                             buffer.drain(..end);
                         }
 
-                        wr.escape_str(&buffer);
+                        write_prose(
+                            wr,
+                            converter,
+                            &buffer,
+                            regex_supsub.as_ref(),
+                            scripts.as_ref(),
+                            linker.as_deref_mut(),
+                        );
                         buffer.clear();
-
-                        header_value = t.to_string();
                     }
                 };
 
-                match event_stack.last().copied().unwrap_or_default() {
-                    EventType::Strong
-                    | EventType::Emphasis
-                    | EventType::Text
-                    | EventType::Header => on_text(&mut writer),
-
-                    EventType::Table | EventType::TableHead => on_text(&mut table_buffer),
+                if in_header {
+                    header_value.push_str(&t);
+                }
 
-                    _ => {
-                        writer.push_str(&*t);
+                match TextContext::of(&event_stack) {
+                    TextContext::Text => {
+                        // `[@key]` citations.
+                        let mut last = 0;
+                        for caps in regex_citation.captures_iter(&t) {
+                            let m = caps.get(0).unwrap();
+                            on_text(out!(), &t[last..m.start()]);
+                            last = m.end();
+
+                            let cited = converter.bibliography.and_then(|bibliography| {
+                                bibliography.write_citation(
+                                    out!(),
+                                    converter.chapter_path,
+                                    &caps["cite"],
+                                )
+                            });
+                            match (cited, converter.citations) {
+                                (Some(keys), Some(citations)) => {
+                                    citations.borrow_mut().extend(keys)
+                                }
+                                (Some(_), None) => {}
+                                (None, _) => on_text(out!(), m.as_str()),
+                            }
+                        }
+                        on_text(out!(), &t[last..]);
+                    }
+                    TextContext::Admonition => admonition_buffer.push_str(&t),
+                    TextContext::Verbatim => {
+                        writer.push_str(&t);
                     }
                 }
             }
 
+            Event::SoftBreak if in_table => {
+                table_buffer.push(' ');
+            }
             Event::SoftBreak => {
                 writer.new_line();
             }
 
+            Event::HardBreak if in_table => {
+                table_buffer.push_str(r"\newline{}");
+            }
             Event::HardBreak => {
                 writer.back_slash().back_slash().new_line();
             }
@@ -577,14 +953,346 @@ fn convert(converter: &Converter) -> String {
     writer.into_buffer()
 }
 
+/// Joins adjacent text events, which pulldown-cmark splits at brackets and other
+/// punctuation, so that markup like `[@key]` arrives in one piece.
+fn merge_text<'a>(
+    events: impl Iterator<Item = (Event<'a>, Range<usize>)>,
+) -> Vec<(Event<'a>, Range<usize>)> {
+    let mut merged: Vec<(Event, Range<usize>)> = Vec::new();
+
+    for (event, range) in events {
+        if let (Event::Text(text), Some((Event::Text(last), last_range))) =
+            (&event, merged.last_mut())
+        {
+            *last = format!("{}{}", last, text).into();
+            last_range.end = range.end;
+            continue;
+        }
+        merged.push((event, range));
+    }
+
+    merged
+}
+
+/// Writes a figure for the image at `src`, relative to the chapter's assets,
+/// or just the image when `inline` (e.g. in a table cell). A caption numbers the figure.
+///
+/// SVG images are converted to PNG in the destination directory first.
+fn write_image(
+    writer: &mut TexWriter<String>,
+    converter: &Converter,
+    src: &str,
+    inline: bool,
+    caption: Option<&Caption>,
+) {
+    let mut assets_path = converter
+        .assets
+        .map(|p| p.to_path_buf())
+        .unwrap_or_default()
+        .join(src);
+
+    let mut path = PathBuf::from_str(src).unwrap();
+
+    // if image path ends with ".svg", run it through
+    // svg2png to convert to png file.
+    if path.extension().unwrap() == "svg" {
+        let img = svg2png(&assets_path);
+
+        path.set_extension("png");
+        let path = path
+            .strip_prefix("../..")
+            .map(Path::to_path_buf)
+            .unwrap_or(path);
+
+        let dest_path = converter
+            .dest
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default()
+            .join(path.clone());
+
+        // create output directories.
+        let _ = fs::create_dir_all(dest_path.parent().unwrap());
+
+        img.save_png(&dest_path).unwrap();
+        assets_path = dest_path;
+    }
+
+    if inline {
+        writer
+            .push_str(r"\includegraphics[width=\linewidth]{")
+            .push_str(assets_path.to_string_lossy().as_ref())
+            .push('}');
+        return;
+    }
+
+    writer
+        .push_str(r"\begin{figure}")
+        .new_line()
+        .push_str(r"\centering")
+        .new_line()
+        .push_str(r"\includegraphics[width=\textwidth]{")
+        .push_str(assets_path.to_string_lossy().as_ref())
+        .push('}')
+        .new_line();
+
+    if let Some(caption) = caption {
+        caption.write(writer, converter.chapter_path);
+        writer.new_line();
+    }
+
+    writer.push_str(r"\end{figure}").new_line();
+}
+
+/// Label of a chapter (`guide/intro.md`) or of a heading within it
+/// (`guide/intro.md:some-heading`), which is unique across the book.
+pub(crate) fn label(chapter: Option<&str>, id: Option<&str>) -> String {
+    let label = match (chapter, id) {
+        (Some(chapter), Some(id)) => format!("{}:{}", chapter, id),
+        (Some(chapter), None) => chapter.to_string(),
+        (None, Some(id)) => id.to_string(),
+        (None, None) => String::new(),
+    };
+
+    escape(&label, EscapeMode::Label)
+}
+
+/// Label targeted by a link to `#id`, `chapter.md` or `chapter.md#id` relative to
+/// the current chapter, or `None` if the link doesn't point into the book.
+pub(crate) fn local_label(chapter: Option<&str>, url: &str) -> Option<String> {
+    if url.contains("://") {
+        return None;
+    }
+
+    let (path, anchor) = url.split_once('#').unwrap_or((url, ""));
+    let anchor = (!anchor.is_empty()).then_some(anchor);
+
+    if path.is_empty() {
+        return Some(label(chapter, anchor));
+    }
+    if !path.ends_with(".md") {
+        return None;
+    }
+
+    // Resolve the path against the current chapter's directory.
+    let mut parts: Vec<&str> = chapter?.split('/').collect();
+    parts.pop();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    Some(label(Some(parts.join("/").as_str()), anchor))
+}
+
+/// Writes inline code with `\texttt`, which unlike `\lstinline` also works in
+/// headings, footnotes and other arguments.
+fn write_code(wr: &mut TexWriter<String>, code: &str) {
+    wr.push_str(r"\texttt{")
+        .push_str(&escape(code, EscapeMode::Code))
+        .push('}');
+}
+
+/// Writes escaped text, linking the first use of glossary terms.
+fn write_prose(
+    wr: &mut TexWriter<String>,
+    converter: &Converter,
+    text: &str,
+    supsub: Option<&Regex>,
+    scripts: Option<&ScriptRuns>,
+    glossary: Option<&mut GlossaryLinker>,
+) {
+    let glossary = match glossary {
+        Some(glossary) => glossary,
+        None => return write_text(wr, converter, text, supsub, scripts),
+    };
+
+    let mut last = 0;
+    for (range, entry) in glossary.first_uses(text) {
+        write_text(wr, converter, &text[last..range.start], supsub, scripts);
+        glossary.write_link(wr, entry);
+        last = range.end;
+    }
+    write_text(wr, converter, &text[last..], supsub, scripts);
+}
+
+/// Writes escaped text, turning `^text^` into superscript and `~text~` into subscript
+/// when enabled, setting
+/// runs in other scripts in their own language and emoji in the emoji font.
+fn write_text(
+    wr: &mut TexWriter<String>,
+    converter: &Converter,
+    text: &str,
+    supsub: Option<&Regex>,
+    scripts: Option<&ScriptRuns>,
+) {
+    let no_rules = BTreeMap::new();
+    let rules = converter.unicode.unwrap_or(&no_rules);
+
+    let escape = |wr: &mut TexWriter<String>, text: &str| {
+        let (images, dest) = (converter.emoji_images, converter.dest);
+        emoji::write_emoji(wr, text, images, dest, |wr, text| match scripts {
+            Some(scripts) => scripts.write(wr, text, rules),
+            None => {
+                wr.push_str(&escape_with(text, EscapeMode::Text, rules));
+            }
+        })
+    };
+    let mut last = 0;
+
+    if let Some(re) = supsub {
+        for caps in re.captures_iter(text) {
+            let m = caps.get(0).unwrap();
+
+            escape(wr, &text[last..m.start()]);
+            match caps.name("sup") {
+                Some(sup) => {
+                    wr.push_str(r"\textsuperscript{");
+                    escape(wr, sup.as_str());
+                }
+                None => {
+                    wr.push_str(r"\textsubscript{");
+                    escape(wr, &caps["sub"]);
+                }
+            }
+            wr.push('}');
+            last = m.end();
+        }
+    }
+
+    escape(wr, &text[last..]);
+}
+
+/// Whether `url` starts with a scheme like `https:` or `mailto:`.
+fn has_scheme(url: &str) -> bool {
+    url.split_once(':').is_some_and(|(scheme, _)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
+}
+
+/// A relative link resolved against the online book at `link_base`.
+///
+/// Returns `None` without a base, and for anchors, absolute paths and URLs with a scheme.
+fn online_url(link_base: Option<&str>, url: &str) -> Option<String> {
+    let base = link_base?;
+    if url.starts_with(['#', '/']) || has_scheme(url) {
+        return None;
+    }
+
+    let (path, anchor) = url.split_once('#').unwrap_or((url, ""));
+    let path = match path.strip_suffix(".md") {
+        Some(stem) => format!("{}.html", stem),
+        None => path.to_string(),
+    };
+
+    let mut target = format!("{}{}", base, path);
+    if !anchor.is_empty() {
+        write!(target, "#{}", anchor).unwrap();
+    }
+    Some(target)
+}
+
+/// Rewrites a paragraph of `Term` and `: definition` lines as a `description` list,
+/// telling terms from definitions by the Markdown `source` of the converted `paragraph`.
+///
+/// Returns `None` if the paragraph isn't a definition list.
+fn definition_list(source: &str, paragraph: &str) -> Option<String> {
+    let definitions: Vec<bool> = source
+        .lines()
+        .map(|l| l.trim_start_matches(['>', ' ', '\t']))
+        .filter(|l| !l.is_empty())
+        .map(|l| l.starts_with(": "))
+        .collect();
+    let lines: Vec<&str> = paragraph.lines().filter(|l| !l.is_empty()).collect();
+
+    // Terms come first, and each line is converted to one line.
+    let first_definition = definitions.iter().position(|&d| d)?;
+    if first_definition == 0 || definitions.len() != lines.len() {
+        return None;
+    }
+
+    let mut list = String::from("\\begin{description}\n");
+    let mut defined = false;
+
+    for (i, line) in lines.iter().enumerate() {
+        // After a definition, the line right before the next one is a new term,
+        // any other line continues the definition.
+        let term =
+            !definitions[i] && (i < first_definition || definitions.get(i + 1) == Some(&true));
+
+        match line.strip_prefix(": ") {
+            _ if term => {
+                defined = false;
+                writeln!(list, "\\item[{{{}}}]", line)
+            }
+            Some(definition) if defined => writeln!(list, "\\par {}", definition),
+            Some(definition) => {
+                defined = true;
+                writeln!(list, "{}", definition)
+            }
+            None => writeln!(list, "{}", line),
+        }
+        .unwrap();
+    }
+
+    list.push_str("\\end{description}\n");
+    Some(list)
+}
+
+/// Admonition kinds with their colours, following mdbook-admonish and GitHub alerts.
+pub const ADMONITION_COLORS: [(&str, &str); 14] = [
+    ("note", "448AFF"),
+    ("abstract", "00B0FF"),
+    ("info", "00B8D4"),
+    ("tip", "00BFA5"),
+    ("success", "00C853"),
+    ("question", "64DD17"),
+    ("warning", "FF9100"),
+    ("failure", "FF5252"),
+    ("danger", "FF1744"),
+    ("bug", "F50057"),
+    ("example", "7C4DFF"),
+    ("quote", "9E9E9E"),
+    ("important", "8250DF"),
+    ("caution", "CF222E"),
+];
+
+/// Opens the template's `admonition` environment, titled after the kind by default.
+fn begin_admonition(writer: &mut TexWriter<String>, kind: &str, title: Option<&str>) {
+    let title = title.map(str::to_string).unwrap_or_else(|| {
+        let mut chars = kind.chars();
+        chars
+            .next()
+            .map(|c| c.to_uppercase().chain(chars).collect())
+            .unwrap_or_default()
+    });
+
+    writer
+        .new_line()
+        .push_str(r"\begin{admonition}{admonition-")
+        .push_str(kind)
+        .push_str("}{")
+        .escape_str(&title)
+        .push('}')
+        .new_line();
+}
+
 pub fn svg2png(filename: &Path) -> Pixmap {
-    let mut opt = usvg::Options::default();
-    opt.resources_dir = std::fs::canonicalize(&filename)
-        .ok()
-        .and_then(|p| p.parent().map(|p| p.to_path_buf()));
+    let opt = usvg::Options {
+        resources_dir: std::fs::canonicalize(filename)
+            .ok()
+            .and_then(|p| p.parent().map(|p| p.to_path_buf())),
+        ..Default::default()
+    };
 
     let rtree = {
-        let opt = usvg::Options::default();
 
     let mut fontdb = fontdb::Database::new();
     fontdb.load_system_fonts();
@@ -601,3 +1309,87 @@ pub fn svg2png(filename: &Path) -> Pixmap {
 
     pixmap
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_are_prefixed_with_the_chapter() {
+        assert_eq!(
+            label(Some("guide/intro.md"), Some("setup")),
+            "guide/intro.md:setup"
+        );
+        assert_eq!(label(None, Some("a{b}")), "a-b-");
+    }
+
+    #[test]
+    fn local_links_resolve_against_the_chapter() {
+        let chapter = Some("guide/intro.md");
+        assert_eq!(
+            local_label(chapter, "#setup").as_deref(),
+            Some("guide/intro.md:setup")
+        );
+        assert_eq!(
+            local_label(chapter, "../faq.md#why").as_deref(),
+            Some("faq.md:why")
+        );
+        assert_eq!(local_label(chapter, "https://example.com/a.md"), None);
+        assert_eq!(local_label(chapter, "image.png"), None);
+    }
+
+    #[test]
+    fn only_the_first_chapter_heading_is_numbered() {
+        let latex = Converter::new("# One\n\n# Two\n")
+            .chapter_level_offset(1)
+            .section_number(&[2, 1])
+            .run();
+        assert!(latex.contains("\\section{One}"));
+        assert!(latex.contains("\\section*{Two}"));
+        assert!(latex.contains("\\addcontentsline{toc}{section}{Two}"));
+        assert!(!latex.contains("\\addcontentsline{toc}{section}{One}"));
+    }
+
+    #[test]
+    fn markdown_in_html_is_converted_like_the_chapter() {
+        let latex = Converter::new(
+            "<div>\n\nSee $$x^2$$.\n\n```rust\nfn main() {}\n```\n\n</div>\n\n\
+             <pre><code class=\"language-python\">print(1)</code></pre>\n",
+        )
+        .run();
+        assert!(latex.contains(r"See \[x^2\]."));
+        assert!(latex.contains(r"\begin{minted}{rust}"));
+        assert!(latex.contains(r"\begin{minted}{python}"));
+    }
+
+    #[test]
+    fn subscripts_within_words() {
+        let extensions = [Extension::Subscript, Extension::Superscript];
+        let latex = Converter::new("H~2~O and x^2^, ~one~ ~~two~~\n")
+            .extensions(&extensions)
+            .run();
+        assert!(latex.contains(r"H\textsubscript{2}O and x\textsuperscript{2}"));
+        assert!(latex.contains(r"\textsubscript{one} \sout{two}"));
+    }
+
+    #[test]
+    fn list_options_need_enumitem() {
+        let source = "3. three\n4. four\n";
+        let latex = Converter::new(source).run();
+        assert!(latex.contains("\\begin{enumerate}[start=3,noitemsep]"));
+
+        let latex = Converter::new(source).enumitem(false).run();
+        assert!(latex.contains("\\begin{enumerate}\n\\setcounter{enumi}{2}\n"));
+    }
+
+    #[test]
+    fn definition_lists_tell_terms_from_continuations() {
+        let source = "Term1\n: def1\nmore of def1\nTerm2\n: def2";
+        let list = definition_list(source, source).unwrap();
+        assert_eq!(
+            list,
+            "\\begin{description}\n\\item[{Term1}]\ndef1\nmore of def1\n\\item[{Term2}]\ndef2\n\\end{description}\n"
+        );
+        assert!(definition_list("Just\ntext", "Just\ntext").is_none());
+    }
+}
@@ -0,0 +1,186 @@
+use super::escape::{escape_with, EscapeMode};
+use super::writer::TexWriter;
+use regex::Regex;
+use std::collections::BTreeMap;
+
+/// Scripts set in their own polyglossia language.
+const SCRIPTS: [(&str, &str); 5] = [
+    ("Latin", "english"),
+    ("Arabic", "arabic"),
+    ("Hebrew", "hebrew"),
+    ("Thai", "thai"),
+    ("Devanagari", "hindi"),
+];
+
+/// Polyglossia languages of `book.language` codes.
+/// Pashto and Sindhi, which polyglossia lacks, are set as Arabic.
+const LANGUAGES: &[(&str, &str)] = &[
+    ("en", "english"),
+    ("de", "german"),
+    ("fr", "french"),
+    ("es", "spanish"),
+    ("it", "italian"),
+    ("pt", "portuguese"),
+    ("nl", "dutch"),
+    ("sv", "swedish"),
+    ("da", "danish"),
+    ("nb", "norwegian"),
+    ("nn", "norwegian"),
+    ("no", "norwegian"),
+    ("fi", "finnish"),
+    ("pl", "polish"),
+    ("cs", "czech"),
+    ("sk", "slovak"),
+    ("hu", "hungarian"),
+    ("ro", "romanian"),
+    ("ca", "catalan"),
+    ("tr", "turkish"),
+    ("id", "indonesian"),
+    ("vi", "vietnamese"),
+    ("ru", "russian"),
+    ("uk", "ukrainian"),
+    ("bg", "bulgarian"),
+    ("sr", "serbian"),
+    ("el", "greek"),
+    ("ar", "arabic"),
+    ("ara", "arabic"),
+    ("ps", "arabic"),
+    ("pus", "arabic"),
+    ("sd", "arabic"),
+    ("fa", "persian"),
+    ("per", "persian"),
+    ("fas", "persian"),
+    ("ur", "urdu"),
+    ("urd", "urdu"),
+    ("ug", "uyghur"),
+    ("ku", "kurdish"),
+    ("kur", "kurdish"),
+    ("dv", "divehi"),
+    ("arc", "syriac"),
+    ("syc", "syriac"),
+    ("syr", "syriac"),
+    ("he", "hebrew"),
+    ("heb", "hebrew"),
+    ("yi", "hebrew"),
+    ("yid", "hebrew"),
+    ("th", "thai"),
+    ("hi", "hindi"),
+    ("mr", "marathi"),
+    ("ne", "nepali"),
+];
+
+/// Polyglossia language of a language code such as `ar` or `he-IL`, `english` if unknown
+/// (CJK text is left to xeCJK).
+pub fn polyglossia_language(code: &str) -> &'static str {
+    let code = code.split(['-', '_']).next().unwrap_or_default();
+
+    LANGUAGES
+        .iter()
+        .find(|(c, _)| c.eq_ignore_ascii_case(code))
+        .map_or("english", |(_, language)| language)
+}
+
+/// Whether a polyglossia language is written right-to-left.
+pub fn is_rtl(language: &str) -> bool {
+    matches!(
+        language,
+        "arabic" | "persian" | "urdu" | "uyghur" | "divehi" | "syriac" | "hebrew"
+    )
+}
+
+/// Languages of the other scripts set in their own language in `latex`.
+pub fn used_languages(latex: &str) -> Vec<&'static str> {
+    SCRIPTS
+        .iter()
+        .map(|(_, language)| *language)
+        .filter(|language| latex.contains(&format!(r"\text{}{{", language)))
+        .collect()
+}
+
+/// Script of a polyglossia language.
+fn main_script(language: &str) -> &'static str {
+    match language {
+        "arabic" | "persian" | "urdu" | "uyghur" => "Arabic",
+        "syriac" => "Syriac",
+        "divehi" => "Thaana",
+        "hebrew" => "Hebrew",
+        "russian" | "ukrainian" | "bulgarian" | "serbian" => "Cyrillic",
+        "greek" => "Greek",
+        "thai" => "Thai",
+        "hindi" | "marathi" | "nepali" => "Devanagari",
+        _ => "Latin",
+    }
+}
+
+/// Finds runs of text in scripts other than the main language's, including the spaces
+/// and punctuation between their words.
+pub(crate) struct ScriptRuns {
+    regex: Regex,
+}
+
+impl ScriptRuns {
+    pub(crate) fn new(main: &str) -> ScriptRuns {
+        let script = main_script(main);
+
+        let alternatives: Vec<String> = SCRIPTS
+            .iter()
+            .filter(|(s, _)| *s != script)
+            .map(|(s, language)| {
+                format!(
+                    r"(?P<{language}>\p{{{s}}}(?:[\p{{{s}}}\p{{Common}}\p{{Inherited}}]*\p{{{s}}})?)",
+                    language = language,
+                    s = s
+                )
+            })
+            .collect();
+
+        ScriptRuns {
+            regex: Regex::new(&alternatives.join("|")).unwrap(),
+        }
+    }
+
+    /// Writes escaped text, with each run in another script in its language's
+    /// `\text<language>` command, so that it's shaped and ordered in its own direction.
+    pub(crate) fn write(
+        &self,
+        wr: &mut TexWriter<String>,
+        text: &str,
+        rules: &BTreeMap<char, String>,
+    ) {
+        let escape = |text| escape_with(text, EscapeMode::Text, rules);
+        let mut last = 0;
+
+        for caps in self.regex.captures_iter(text) {
+            let m = caps.get(0).unwrap();
+            let language = SCRIPTS
+                .iter()
+                .map(|(_, language)| *language)
+                .find(|language| caps.name(language).is_some())
+                .unwrap();
+
+            wr.push_str(&escape(&text[last..m.start()]))
+                .push_str(r"\text")
+                .push_str(language)
+                .push('{')
+                .push_str(&escape(m.as_str()))
+                .push('}');
+            last = m.end();
+        }
+
+        wr.push_str(&escape(&text[last..]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_language_codes() {
+        assert_eq!(polyglossia_language("de-AT"), "german");
+        assert_eq!(polyglossia_language("ug"), "uyghur");
+        assert_eq!(polyglossia_language("zh-CN"), "english");
+        assert!(is_rtl(polyglossia_language("syr")));
+        assert!(!is_rtl(polyglossia_language("ru")));
+    }
+}
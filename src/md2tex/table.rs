@@ -0,0 +1,88 @@
+use super::caption::Caption;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// How Markdown tables are drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TableStyle {
+    /// Vertical rules and light grey lines between rows.
+    #[default]
+    Grid,
+    /// `booktabs` rules above and below the table and under the header only.
+    Booktabs,
+    /// Alternating row colours without vertical rules.
+    Zebra,
+}
+
+impl TableStyle {
+    /// Separator between column specifications.
+    pub(crate) fn column_rule(self) -> &'static str {
+        match self {
+            TableStyle::Grid => "|",
+            TableStyle::Booktabs | TableStyle::Zebra => "",
+        }
+    }
+
+    pub(crate) fn top_rule(self) -> &'static str {
+        match self {
+            TableStyle::Grid | TableStyle::Zebra => r"\hline",
+            TableStyle::Booktabs => r"\toprule",
+        }
+    }
+
+    pub(crate) fn head_rule(self) -> &'static str {
+        match self {
+            TableStyle::Grid | TableStyle::Zebra => r"\hline",
+            TableStyle::Booktabs => r"\midrule",
+        }
+    }
+
+    pub(crate) fn row_rule(self) -> &'static str {
+        match self {
+            TableStyle::Grid => r"\arrayrulecolor{lightgray}\hline",
+            TableStyle::Booktabs | TableStyle::Zebra => "",
+        }
+    }
+
+    pub(crate) fn bottom_rule(self) -> &'static str {
+        match self {
+            TableStyle::Grid => r"\arrayrulecolor{black}\hline",
+            TableStyle::Zebra => r"\hline",
+            TableStyle::Booktabs => r"\bottomrule",
+        }
+    }
+
+    /// Background of the header row and of every other body row.
+    pub(crate) fn row_color(self, head: bool, row: usize) -> Option<&'static str> {
+        match (self, head) {
+            (TableStyle::Zebra, true) => Some(r"\rowcolor{gray!30}"),
+            (TableStyle::Zebra, false) if row % 2 == 1 => Some(r"\rowcolor{gray!10}"),
+            _ => None,
+        }
+    }
+}
+
+/// Caption from a `Table: caption` paragraph, if the source right after it is a table.
+pub(crate) fn paragraph_caption(paragraph: &str, rest: &str) -> Option<Caption> {
+    let caption = paragraph.trim().strip_prefix("Table:")?;
+    if caption.contains('\n') {
+        return None;
+    }
+
+    let regex_table = Regex::new(
+        r"^\s*[^\n]*\|[^\n]*\n[ \t]*\|?[ \t]*:?-+:?[ \t]*(\|[ \t]*:?-+:?[ \t]*)*\|?[ \t]*(\r?\n|$)",
+    )
+    .unwrap();
+
+    regex_table.is_match(rest).then(|| Caption::new(caption))
+}
+
+/// Caption from a `<!-- caption: ... -->` comment.
+pub(crate) fn comment_caption(html: &str) -> Option<Caption> {
+    let regex_comment = Regex::new(r"(?s)^\s*<!--\s*caption:\s*(.*?)\s*-->\s*$").unwrap();
+
+    regex_comment
+        .captures(html)
+        .map(|cap| Caption::new(&cap[1]))
+}
@@ -1,3 +1,4 @@
+use super::escape::{escape, EscapeMode};
 use std::fmt::Write;
 
 #[derive(Debug)]
@@ -26,7 +27,7 @@ impl<T: Write> TexWriter<T> {
     }
 
     pub fn escape_str(&mut self, s: &str) -> &mut Self {
-        let escaped = escape_tex_text(s);
+        let escaped = escape(s, EscapeMode::Text);
         self.push_str(&escaped)
     }
 
@@ -73,16 +74,3 @@ impl<T: Write> Write for TexWriter<T> {
         self.buffer.write_str(s)
     }
 }
-
-fn escape_tex_text(md: &str) -> String {
-    md.replace(r"\", r"\\")
-        .replace("&", r"\&")
-        .replace(r"\s", r"\textbackslash{}s")
-        .replace(r"\w", r"\textbackslash{}w")
-        .replace("_", r"\_")
-        .replace(r"\<", "<")
-        .replace(r"%", r"\%")
-        .replace(r"$", r"\$")
-        .replace(r"—", "---")
-        .replace("#", r"\#")
-}